@@ -0,0 +1,205 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use tracing::info;
+
+use crate::feeds::{Channel, Feeds};
+use crate::pages::Page;
+use crate::sitemap;
+use crate::tmpl::{render_blogpost, render_postlist, render_tagged_post_list};
+use crate::types::{Post, PostDiagnostic, PostParseError, Visibility};
+
+/// Everything the site serves that's derived from the posts on disk.
+/// A snapshot is built in one go and never changed afterwards. Reloading builds
+/// a whole new one and swaps it in, so a request sees either all of the old
+/// site or all of the new one, never a mix.
+pub struct Content {
+    /// Newest first.
+    pub posts: Vec<Post>,
+    pub feeds: Feeds,
+    pub sitemap: Page,
+    pub diagnostics: Vec<PostDiagnostic>,
+    /// The list of every post, ready to send.
+    pub post_list: Page,
+    /// Posts that aren't out yet or are private, by slug. Only reachable
+    /// through a preview link.
+    drafts: HashMap<String, Post>,
+    /// Slug to index in `posts`.
+    slugs: HashMap<String, usize>,
+    /// Tag to indices in `posts`, newest first. Unlisted posts are left out
+    /// unless we're in debug mode.
+    tags: HashMap<String, Vec<usize>>,
+    /// Rendered pages for each post and tag, keyed the same as above.
+    post_pages: HashMap<String, Page>,
+    tag_pages: HashMap<String, Page>,
+    tag_feeds: HashMap<String, Feeds>,
+    /// When the snapshot was built, the time it shows the site as of.
+    built: DateTime<Utc>,
+}
+
+impl Content {
+    /// Index the posts and render every page that comes from them. In debug
+    /// mode every post is served and gets its problems shown on the page,
+    /// otherwise drafts are set aside for previews. Feeds hold at most
+    /// `feed_length` posts, anything older goes into their archives. Which
+    /// posts are out yet is decided as of `built`.
+    pub fn new(
+        posts: Vec<Post>,
+        diagnostics: Vec<PostDiagnostic>,
+        debug: bool,
+        feed_length: usize,
+        built: DateTime<Utc>,
+    ) -> Self {
+        let (posts, drafts): (Vec<Post>, Vec<Post>) = posts
+            .into_iter()
+            .partition(|post| debug || post.is_served(built));
+        for draft in drafts.iter() {
+            info!(
+                "Post \"{}\" is either not due to be published, or set to private. Only previewing it.",
+                draft.frontmatter.title
+            );
+        }
+        let drafts = drafts
+            .into_iter()
+            .map(|post| (post.frontmatter.slug.clone(), post))
+            .collect();
+        let mut slugs = HashMap::new();
+        let mut tags: HashMap<String, Vec<usize>> = HashMap::new();
+        for (i, post) in posts.iter().enumerate() {
+            // Duplicate slugs only make it this far in debug mode. First one
+            // wins, same as the old linear scan.
+            slugs.entry(post.frontmatter.slug.clone()).or_insert(i);
+            if !debug && !post.is_listed(built) {
+                continue;
+            }
+            for tag in post.frontmatter.tags.iter() {
+                tags.entry(tag.clone()).or_default().push(i);
+            }
+        }
+        // Unlisted posts can still be read by anyone with the link, they just
+        // aren't handed out anywhere.
+        let listed: Vec<&Post> = posts.iter().filter(|post| post.is_listed(built)).collect();
+        // Pages listing several posts change whenever one goes away, which
+        // no post's own dates can tell us about, so they're as new as the
+        // snapshot.
+        let feeds = Feeds::new(&Channel::site(), &listed, feed_length, built);
+        let sitemap = Page::new(
+            sitemap::sitemap(
+                &listed,
+                tags.iter().filter_map(|(tag, indices)| {
+                    let posts: Vec<&Post> = indices
+                        .iter()
+                        .map(|&i| &posts[i])
+                        .filter(|post| post.is_listed(built))
+                        .collect();
+                    (!posts.is_empty()).then_some((tag.as_str(), posts))
+                }),
+            ),
+            sitemap::CONTENT_TYPE,
+            Some(built),
+        );
+        let mut content = Self {
+            post_list: Page::html(
+                render_postlist(posts.iter().collect(), debug, built).into_string(),
+                Some(built),
+            ),
+            posts,
+            feeds,
+            sitemap,
+            diagnostics,
+            drafts,
+            slugs,
+            tags,
+            post_pages: HashMap::new(),
+            tag_pages: HashMap::new(),
+            tag_feeds: HashMap::new(),
+            built,
+        };
+        content.post_pages = content
+            .slugs
+            .iter()
+            .map(|(slug, &i)| {
+                let problems = match debug {
+                    true => content.problems_for(slug),
+                    false => Vec::new(),
+                };
+                let post = &content.posts[i];
+                let page = render_blogpost(post, &problems, false).into_string();
+                (slug.clone(), Page::html(page, Some(post.last_modified())))
+            })
+            .collect();
+        content.tag_pages = content
+            .tags
+            .keys()
+            .filter_map(|tag| {
+                let posts = content.tagged(tag);
+                let page = render_tagged_post_list(posts, tag, debug, built).ok()?;
+                Some((tag.clone(), Page::html(page.into_string(), Some(built))))
+            })
+            .collect();
+        content.tag_feeds = content
+            .tags
+            .keys()
+            .map(|tag| {
+                let mut posts = content.tagged(tag);
+                posts.retain(|post| post.is_listed(built));
+                let feeds = Feeds::new(&Channel::tag(tag), &posts, feed_length, built);
+                (tag.clone(), feeds)
+            })
+            .collect();
+        content
+    }
+
+    pub fn post(&self, slug: &str) -> Option<&Post> {
+        self.slugs.get(slug).map(|&i| &self.posts[i])
+    }
+
+    /// A post anyone holding a valid preview link for it may see, whether
+    /// or not it's out yet.
+    pub fn preview(&self, slug: &str) -> Option<&Post> {
+        self.post(slug).or_else(|| self.drafts.get(slug))
+    }
+
+    /// When the next post that's waiting to go out is due. A new snapshot
+    /// has to be built then for it to show up.
+    pub fn next_publication(&self) -> Option<DateTime<Utc>> {
+        self.posts
+            .iter()
+            .chain(self.drafts.values())
+            .filter(|post| post.frontmatter.visibility != Visibility::Private)
+            .map(Post::published_at)
+            .filter(|&published| published > self.built)
+            .min()
+    }
+
+    pub fn post_page(&self, slug: &str) -> Option<&Page> {
+        self.post_pages.get(slug)
+    }
+
+    pub fn tag_page(&self, tag: &str) -> Option<&Page> {
+        self.tag_pages.get(tag)
+    }
+
+    pub fn tag_feeds(&self, tag: &str) -> Option<&Feeds> {
+        self.tag_feeds.get(tag)
+    }
+
+    /// Every post with the given tag, newest first.
+    pub fn tagged(&self, tag: &str) -> Vec<&Post> {
+        self.tags
+            .get(tag)
+            .map(|indices| indices.iter().map(|&i| &self.posts[i]).collect())
+            .unwrap_or_default()
+    }
+
+    /// Problems that stop a post being published, for showing on its page.
+    pub fn problems_for(&self, slug: &str) -> Vec<PostDiagnostic> {
+        self.diagnostics
+            .iter()
+            .filter(
+                |d| matches!(&d.error, PostParseError::DuplicateSlug { slug: s, .. } if s == slug),
+            )
+            .cloned()
+            .collect()
+    }
+}
@@ -33,6 +33,7 @@ impl Accept for ServerAccept {
     }
 }
 
+#[allow(dead_code)]
 pub struct ClientConnection {
     pub stream: UnixStream,
 }
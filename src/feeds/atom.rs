@@ -0,0 +1,296 @@
+use chrono::{DateTime, Utc};
+use quick_xml::events::{BytesDecl, BytesText, Event};
+use quick_xml::{Result, Writer};
+
+use super::{
+    rfc3339, summary, text, updated, xml, Channel, ATOM_NS, AUTHOR_EMAIL, AUTHOR_NAME, SITE_URL,
+    STYLESHEET_PI,
+};
+use crate::types::Post;
+
+const HISTORY_NS: &str = "http://purl.org/syndication/history/1.0";
+
+pub const CONTENT_TYPE: &str = "application/atom+xml";
+
+/// Which part of an RFC 5005 archived feed a document is. Archives are
+/// numbered from 1, the oldest.
+#[derive(Debug, Clone, Copy)]
+pub enum Document {
+    /// The feed readers poll, which points back at the newest archive.
+    Subscription {
+        archives: usize,
+    },
+    Archive {
+        page: usize,
+        archives: usize,
+    },
+}
+
+/// Build an Atom document for the given posts, which should be newest first.
+pub fn feed(
+    channel: &Channel,
+    posts: &[&Post],
+    document: Document,
+    built: DateTime<Utc>,
+) -> String {
+    xml(|writer| write_feed(writer, channel, posts, document, built))
+}
+
+fn write_feed(
+    writer: &mut Writer<Vec<u8>>,
+    channel: &Channel,
+    posts: &[&Post],
+    document: Document,
+    built: DateTime<Utc>,
+) -> Result<()> {
+    let subscription = channel.url("atom.xml");
+    let archive = |page: usize| channel.url(&format!("archive/{}", page));
+    writer.write_event(Event::Decl(BytesDecl::new("1.0", Some("UTF-8"), None)))?;
+    writer.write_event(Event::PI(BytesText::from_escaped(STYLESHEET_PI)))?;
+    writer
+        .create_element("feed")
+        .with_attribute(("xmlns", ATOM_NS))
+        .with_attribute(("xmlns:fh", HISTORY_NS))
+        .write_inner_content(|writer| {
+            text(writer, "id", &channel.id)?;
+            text(writer, "title", &channel.title)?;
+            text(writer, "updated", &rfc3339(updated(posts, built)))?;
+            author(writer)?;
+            match document {
+                Document::Subscription { archives } => {
+                    link(writer, "self", &subscription)?;
+                    if archives > 0 {
+                        link(writer, "prev-archive", &archive(archives))?;
+                    }
+                }
+                Document::Archive { page, archives } => {
+                    writer.create_element("fh:archive").write_empty()?;
+                    link(writer, "self", &archive(page))?;
+                    link(writer, "current", &subscription)?;
+                    if page > 1 {
+                        link(writer, "prev-archive", &archive(page - 1))?;
+                    }
+                    if page < archives {
+                        link(writer, "next-archive", &archive(page + 1))?;
+                    }
+                }
+            }
+            writer
+                .create_element("link")
+                .with_attribute(("rel", "alternate"))
+                .with_attribute(("type", "text/html"))
+                .with_attribute(("href", channel.home_page_url().as_str()))
+                .write_empty()?;
+            writer
+                .create_element("generator")
+                .with_attribute(("uri", SITE_URL))
+                .write_text_content(BytesText::new("whydoesntmycode.work"))?;
+            for post in posts.iter() {
+                entry(writer, post)?;
+            }
+            Ok(())
+        })?;
+    Ok(())
+}
+
+fn entry(writer: &mut Writer<Vec<u8>>, post: &Post) -> Result<()> {
+    let url = post.get_full_url();
+    let mut entry = writer.create_element("entry");
+    if let Some(lang) = &post.frontmatter.lang {
+        entry = entry.with_attribute(("xml:lang", lang.as_str()));
+    }
+    entry.write_inner_content(|writer| {
+        text(writer, "id", &url)?;
+        text(writer, "title", &post.frontmatter.title)?;
+        text(writer, "published", &rfc3339(post.published_at()))?;
+        text(writer, "updated", &rfc3339(post.updated_at()))?;
+        post_author(writer, post)?;
+        writer
+            .create_element("link")
+            .with_attribute(("rel", "alternate"))
+            .with_attribute(("type", "text/html"))
+            .with_attribute(("href", url.as_str()))
+            .write_empty()?;
+        for tag in post.frontmatter.tags.iter() {
+            writer
+                .create_element("category")
+                .with_attribute(("term", tag.as_str()))
+                .write_empty()?;
+        }
+        text(writer, "summary", &summary(post))?;
+        writer
+            .create_element("content")
+            .with_attribute(("type", "html"))
+            .with_attribute(("xml:base", url.as_str()))
+            .write_text_content(BytesText::new(&post.rendered))?;
+        Ok(())
+    })?;
+    Ok(())
+}
+
+fn link(writer: &mut Writer<Vec<u8>>, rel: &str, href: &str) -> Result<()> {
+    writer
+        .create_element("link")
+        .with_attribute(("rel", rel))
+        .with_attribute(("type", CONTENT_TYPE))
+        .with_attribute(("href", href))
+        .write_empty()?;
+    Ok(())
+}
+
+fn author(writer: &mut Writer<Vec<u8>>) -> Result<()> {
+    writer
+        .create_element("author")
+        .write_inner_content(|writer| {
+            text(writer, "name", AUTHOR_NAME)?;
+            text(writer, "email", AUTHOR_EMAIL)?;
+            Ok(())
+        })?;
+    Ok(())
+}
+
+/// Only the site's own author has an email address we can give out.
+fn post_author(writer: &mut Writer<Vec<u8>>, post: &Post) -> Result<()> {
+    if post.frontmatter.author.is_none() {
+        return author(writer);
+    }
+    writer
+        .create_element("author")
+        .write_inner_content(|writer| {
+            text(writer, "name", post.author())?;
+            Ok(())
+        })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::feeds::tests::{parse, post, Node};
+    use chrono::NaiveDate;
+    use std::collections::HashSet;
+
+    fn assert_date(node: &Node) {
+        DateTime::parse_from_rfc3339(&node.text)
+            .unwrap_or_else(|_| panic!("<{}> isn't RFC 3339: {}", node.name, node.text));
+    }
+
+    #[test]
+    fn follows_the_spec() {
+        let posts = [
+            post("Tom & Jerry <3", "tom-and-jerry", None),
+            post("Second", "second", NaiveDate::from_ymd_opt(2023, 5, 1)),
+        ];
+        let xml = feed(
+            &Channel::site(),
+            &posts.iter().collect::<Vec<_>>(),
+            Document::Subscription { archives: 0 },
+            Utc::now(),
+        );
+        assert!(
+            xml.contains("<?xml-stylesheet type=\"text/xsl\" href=\"/static/feeds/feed.xsl\"?>")
+        );
+        let feed = parse(&xml);
+
+        assert_eq!(feed.name, "feed");
+        assert_eq!(feed.attributes["xmlns"], ATOM_NS);
+        feed.one("id");
+        feed.one("title");
+        assert_date(feed.one("updated"));
+        assert_eq!(feed.one("updated").text, "2023-05-01T00:00:00Z");
+        assert!(!feed.one("author").one("name").text.is_empty());
+        let self_links: Vec<_> = feed
+            .all("link")
+            .into_iter()
+            .filter(|l| l.attributes["rel"] == "self")
+            .collect();
+        assert_eq!(self_links.len(), 1);
+        assert_eq!(
+            self_links[0].attributes["href"],
+            "https://whydoesntmycode.work/feeds/atom.xml"
+        );
+
+        let entries = feed.all("entry");
+        assert_eq!(entries.len(), 2);
+        let mut ids = HashSet::new();
+        for entry in entries.iter() {
+            assert!(ids.insert(entry.one("id").text.clone()), "Duplicate id");
+            assert!(entry.one("id").text.starts_with("https://"));
+            entry.one("title");
+            assert_date(entry.one("published"));
+            assert_date(entry.one("updated"));
+            entry.one("summary");
+            assert_eq!(entry.one("content").attributes["type"], "html");
+            assert!(entry
+                .all("link")
+                .iter()
+                .any(|l| l.attributes["rel"] == "alternate"));
+            for category in entry.all("category") {
+                assert!(!category.attributes["term"].is_empty());
+            }
+        }
+
+        let first = entries[0];
+        assert_eq!(first.one("title").text, "Tom & Jerry <3");
+        assert_eq!(first.one("updated").text, first.one("published").text);
+        assert_eq!(first.one("summary").text, "Fish & chips <3 More words.");
+        assert_eq!(
+            first.one("content").text,
+            "<p>Fish &amp; chips &lt;3</p><p>More <em>words</em>.</p>"
+        );
+        let terms: Vec<_> = first
+            .all("category")
+            .iter()
+            .map(|c| c.attributes["term"].clone())
+            .collect();
+        assert_eq!(terms, ["rust", "c&c"]);
+    }
+
+    #[test]
+    fn empty_feeds_are_still_valid() {
+        let built = DateTime::parse_from_rfc3339("2023-06-01T12:00:00Z").unwrap();
+        let feed = parse(&feed(
+            &Channel::site(),
+            &[],
+            Document::Subscription { archives: 0 },
+            built.with_timezone(&Utc),
+        ));
+        // With no posts to go by, the feed is as new as the build.
+        assert_eq!(feed.one("updated").text, "2023-06-01T12:00:00Z");
+        assert!(feed.all("entry").is_empty());
+    }
+
+    #[test]
+    fn tag_feeds_point_at_themselves() {
+        let posts = [post("Tagged", "tagged", None)];
+        let feed = parse(&feed(
+            &Channel::tag("rust"),
+            &posts.iter().collect::<Vec<_>>(),
+            Document::Subscription { archives: 0 },
+            Utc::now(),
+        ));
+        assert_eq!(
+            feed.one("id").text,
+            "https://whydoesntmycode.work/tag/rust/atom.xml"
+        );
+        assert_eq!(feed.one("title").text, "Why Doesn't My Code Work? #rust");
+        let hrefs: Vec<_> = feed
+            .all("link")
+            .iter()
+            .map(|l| (l.attributes["rel"].clone(), l.attributes["href"].clone()))
+            .collect();
+        assert_eq!(
+            hrefs,
+            [
+                (
+                    "self".to_string(),
+                    "https://whydoesntmycode.work/tag/rust/atom.xml".to_string()
+                ),
+                (
+                    "alternate".to_string(),
+                    "https://whydoesntmycode.work/tag/rust".to_string()
+                )
+            ]
+        );
+    }
+}
@@ -0,0 +1,132 @@
+use serde::Serialize;
+
+use super::{rfc3339, summary, Channel, AUTHOR_NAME, SITE_URL};
+use crate::types::Post;
+
+const VERSION: &str = "https://jsonfeed.org/version/1.1";
+
+pub const CONTENT_TYPE: &str = "application/feed+json";
+
+#[derive(Serialize)]
+struct Feed<'a> {
+    version: &'static str,
+    title: &'a str,
+    home_page_url: String,
+    feed_url: String,
+    language: &'static str,
+    authors: Vec<Author<'a>>,
+    items: Vec<Item<'a>>,
+}
+
+#[derive(Serialize)]
+struct Author<'a> {
+    name: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url: Option<&'static str>,
+}
+
+#[derive(Serialize)]
+struct Item<'a> {
+    id: String,
+    url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    external_url: Option<String>,
+    title: &'a str,
+    content_html: &'a str,
+    summary: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    image: Option<String>,
+    date_published: String,
+    date_modified: String,
+    tags: &'a [String],
+    authors: Vec<Author<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    language: Option<&'a str>,
+}
+
+fn authors() -> Vec<Author<'static>> {
+    vec![Author {
+        name: AUTHOR_NAME,
+        url: Some(SITE_URL),
+    }]
+}
+
+fn post_authors(post: &Post) -> Vec<Author<'_>> {
+    match &post.frontmatter.author {
+        Some(name) => vec![Author { name, url: None }],
+        None => authors(),
+    }
+}
+
+/// Build the JSON feed for the given posts, which should be newest first.
+pub fn feed(channel: &Channel, posts: &[&Post]) -> String {
+    let feed = Feed {
+        version: VERSION,
+        title: &channel.title,
+        home_page_url: channel.home_page_url(),
+        feed_url: channel.url("feed.json"),
+        language: "en",
+        authors: authors(),
+        items: posts
+            .iter()
+            .map(|post| Item {
+                id: post.get_full_url(),
+                url: post.get_full_url(),
+                external_url: post.frontmatter.canonical_url.clone(),
+                title: &post.frontmatter.title,
+                content_html: &post.rendered,
+                summary: summary(post),
+                image: post.cover_image_url(),
+                date_published: rfc3339(post.published_at()),
+                date_modified: rfc3339(post.updated_at()),
+                tags: &post.frontmatter.tags,
+                authors: post_authors(post),
+                language: post.frontmatter.lang.as_deref(),
+            })
+            .collect(),
+    };
+    serde_json::to_string_pretty(&feed).expect("A feed is always valid JSON")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::feeds::tests::post;
+    use chrono::NaiveDate;
+    use serde_json::Value;
+
+    #[test]
+    fn follows_the_spec() {
+        let posts = [
+            post("Tom & Jerry <3", "tom-and-jerry", None),
+            post("Second", "second", NaiveDate::from_ymd_opt(2023, 5, 1)),
+        ];
+        let feed: Value =
+            serde_json::from_str(&feed(&Channel::site(), &posts.iter().collect::<Vec<_>>()))
+                .unwrap();
+
+        assert_eq!(feed["version"], VERSION);
+        assert!(feed["title"].is_string());
+        assert_eq!(
+            feed["feed_url"],
+            "https://whydoesntmycode.work/feeds/feed.json"
+        );
+        let items = feed["items"].as_array().unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(
+            items[0]["id"],
+            "https://whydoesntmycode.work/post/tom-and-jerry"
+        );
+        assert_eq!(items[0]["title"], "Tom & Jerry <3");
+        assert_eq!(items[0]["date_published"], "2023-04-05T00:00:00Z");
+        assert_eq!(items[0]["date_modified"], "2023-04-05T00:00:00Z");
+        assert_eq!(items[1]["date_modified"], "2023-05-01T00:00:00Z");
+        assert_eq!(items[0]["tags"], serde_json::json!(["rust", "c&c"]));
+        assert_eq!(items[0]["summary"], "Fish & chips <3 More words.");
+        assert_eq!(items[0]["authors"][0]["name"], AUTHOR_NAME);
+        assert!(items[0]["content_html"]
+            .as_str()
+            .unwrap()
+            .starts_with("<p>Fish"));
+    }
+}
@@ -0,0 +1,470 @@
+pub mod atom;
+pub mod json;
+pub mod rss;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use quick_xml::events::BytesText;
+use quick_xml::{Result, Writer};
+
+use crate::pages::Page;
+use crate::types::Post;
+
+pub const SITE_URL: &str = "https://whydoesntmycode.work";
+pub const SITE_TITLE: &str = "Why Doesn't My Code Work?";
+pub const AUTHOR_NAME: &str = "Freyja";
+pub const AUTHOR_EMAIL: &str = "rncwnd@whydoesntmycode.work";
+
+pub const ATOM_NS: &str = "http://www.w3.org/2005/Atom";
+
+/// Points browsers at a stylesheet that turns a feed into a readable page.
+/// Feed readers ignore it.
+const STYLESHEET_PI: &str = "xml-stylesheet type=\"text/xsl\" href=\"/static/feeds/feed.xsl\"";
+
+/// The site feed's id has to stay the same forever or readers treat it as a
+/// new feed, so this is still the old address even though nothing is served
+/// there.
+const SITE_FEED_ID: &str = "https://whydoesntmycode.work/blog.atom";
+
+/// Everything but the characters RFC 3986 leaves unreserved gets escaped in a
+/// tag's path segment, so tags can hold spaces, `&`, `/` and so on.
+const PATH_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// What a set of feeds is of, either the whole site or a single tag.
+pub struct Channel {
+    pub title: String,
+    /// Where the feeds are served from, each format's file name goes on the end.
+    pub path: String,
+    /// The page the feeds mirror.
+    pub home_page: String,
+    id: String,
+}
+
+impl Channel {
+    pub fn site() -> Self {
+        Channel {
+            title: SITE_TITLE.to_string(),
+            path: "/feeds".to_string(),
+            home_page: "/blog".to_string(),
+            id: SITE_FEED_ID.to_string(),
+        }
+    }
+
+    pub fn tag(tag: &str) -> Self {
+        let path = format!("/tag/{}", utf8_percent_encode(tag, PATH_SEGMENT));
+        Channel {
+            title: format!("{} #{}", SITE_TITLE, tag),
+            id: format!("{}{}/atom.xml", SITE_URL, path),
+            home_page: path.clone(),
+            path,
+        }
+    }
+
+    fn url(&self, file: &str) -> String {
+        format!("{}{}/{}", SITE_URL, self.path, file)
+    }
+
+    fn home_page_url(&self) -> String {
+        format!("{}{}", SITE_URL, self.home_page)
+    }
+}
+
+/// A channel's posts in every format we offer.
+pub struct Feeds {
+    pub atom: Page,
+    pub rss: Page,
+    pub json: Page,
+    /// Older posts that have dropped out of the Atom feed, oldest page first.
+    archives: Vec<Page>,
+}
+
+impl Feeds {
+    /// Build every feed for a channel. `posts` should be newest first, and
+    /// only the newest `length` of them go in the feeds. The rest go into
+    /// Atom archive pages of `length` posts each, so `length` can't be 0. Pages are cut from the oldest post
+    /// onwards and only once full, so a page never changes once it exists.
+    /// That leaves some overlap between the newest archive and the feed,
+    /// which readers sort out by entry id. Every feed is marked as last
+    /// modified at `built`, as a post dropping out changes them too.
+    pub fn new(channel: &Channel, posts: &[&Post], length: usize, built: DateTime<Utc>) -> Self {
+        assert!(length > 0, "Feeds have to hold at least one post");
+        let oldest_first: Vec<&Post> = posts.iter().rev().copied().collect();
+        let count = posts.len().saturating_sub(1) / length;
+        let archives = oldest_first
+            .chunks(length)
+            .take(count)
+            .enumerate()
+            .map(|(i, chunk)| {
+                let page: Vec<&Post> = chunk.iter().rev().copied().collect();
+                let document = atom::Document::Archive {
+                    page: i + 1,
+                    archives: count,
+                };
+                Page::new(
+                    atom::feed(channel, &page, document, built),
+                    atom::CONTENT_TYPE,
+                    Some(built),
+                )
+            })
+            .collect();
+        let newest = &posts[..posts.len().min(length)];
+        let document = atom::Document::Subscription { archives: count };
+        let last_modified = Some(built);
+        Feeds {
+            atom: Page::new(
+                atom::feed(channel, newest, document, built),
+                atom::CONTENT_TYPE,
+                last_modified,
+            ),
+            rss: Page::new(
+                rss::feed(channel, newest, built),
+                rss::CONTENT_TYPE,
+                last_modified,
+            ),
+            json: Page::new(
+                json::feed(channel, newest),
+                json::CONTENT_TYPE,
+                last_modified,
+            ),
+            archives,
+        }
+    }
+
+    /// An Atom archive page, counting from 1.
+    pub fn archive(&self, page: usize) -> Option<&Page> {
+        page.checked_sub(1).and_then(|i| self.archives.get(i))
+    }
+}
+
+/// How long a summary pulled out of a post's body is allowed to get.
+const SUMMARY_LENGTH: usize = 280;
+
+const INLINE_ELEMENTS: &[&str] = &["a", "b", "code", "del", "em", "i", "span", "strong", "sup"];
+
+/// Build an XML document in memory with `write`.
+pub fn xml(write: impl FnOnce(&mut Writer<Vec<u8>>) -> Result<()>) -> String {
+    let mut writer = Writer::new_with_indent(Vec::new(), b' ', 2);
+    write(&mut writer).expect("Writing XML into memory can't fail");
+    String::from_utf8(writer.into_inner()).expect("Everything we write is UTF-8")
+}
+
+/// An element holding nothing but `content`, escaped.
+pub fn text(writer: &mut Writer<Vec<u8>>, name: &str, content: &str) -> Result<()> {
+    writer
+        .create_element(name)
+        .write_text_content(BytesText::new(content))?;
+    Ok(())
+}
+
+fn rfc3339(date: DateTime<Utc>) -> String {
+    date.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// When the newest of these posts was updated. An empty feed still needs a
+/// date, so it gets when it was built.
+fn updated(posts: &[&Post], built: DateTime<Utc>) -> DateTime<Utc> {
+    posts
+        .iter()
+        .map(|post| post.updated_at())
+        .max()
+        .unwrap_or(built)
+}
+
+/// A short plain text summary of a post. That's its description if it has
+/// one, otherwise it's taken from the start of its body.
+fn summary(post: &Post) -> String {
+    if let Some(description) = &post.frontmatter.description {
+        return description.clone();
+    }
+    let mut text = String::new();
+    let mut tag: Option<String> = None;
+    for c in post.rendered.chars() {
+        match (&mut tag, c) {
+            (None, '<') => tag = Some(String::new()),
+            (Some(name), '>') => {
+                // Block elements get a gap so paragraphs don't run together.
+                let name = name.trim_start_matches('/');
+                let name = name.split_whitespace().next().unwrap_or_default();
+                if !INLINE_ELEMENTS.contains(&name) {
+                    text.push(' ');
+                }
+                tag = None;
+            }
+            (Some(name), c) => name.push(c),
+            (None, c) => text.push(c),
+        }
+    }
+    let text = quick_xml::escape::unescape(&text)
+        .map(|t| t.into_owned())
+        .unwrap_or(text);
+    let mut summary = String::new();
+    for word in text.split_whitespace() {
+        if summary.len() + word.len() + 1 > SUMMARY_LENGTH {
+            summary.push('…');
+            break;
+        }
+        if !summary.is_empty() {
+            summary.push(' ');
+        }
+        summary.push_str(word);
+    }
+    summary
+}
+
+/// Bits the tests for each kind of feed share.
+#[cfg(test)]
+pub mod tests {
+    use super::{Channel, Feeds};
+    use crate::frontmatter::Timestamp;
+    use crate::pages::{Encoding, Page};
+    use crate::types::{FrontMatter, Post, Visibility};
+    use chrono::{NaiveDate, Utc};
+    use chrono_tz::Tz;
+    use quick_xml::events::{BytesStart, Event};
+    use quick_xml::Reader;
+    use std::collections::HashMap;
+
+    /// Just enough of a DOM to check the feed against the spec.
+    #[derive(Debug, Default)]
+    pub struct Node {
+        pub name: String,
+        pub attributes: HashMap<String, String>,
+        pub children: Vec<Node>,
+        pub text: String,
+    }
+
+    impl Node {
+        fn new(start: &BytesStart) -> Node {
+            Node {
+                name: String::from_utf8(start.name().as_ref().to_vec()).unwrap(),
+                attributes: start
+                    .attributes()
+                    .map(|a| {
+                        let a = a.unwrap();
+                        (
+                            String::from_utf8(a.key.as_ref().to_vec()).unwrap(),
+                            a.unescape_value().unwrap().into_owned(),
+                        )
+                    })
+                    .collect(),
+                ..Default::default()
+            }
+        }
+
+        pub fn all(&self, name: &str) -> Vec<&Node> {
+            self.children.iter().filter(|c| c.name == name).collect()
+        }
+
+        pub fn one(&self, name: &str) -> &Node {
+            let found = self.all(name);
+            assert_eq!(
+                found.len(),
+                1,
+                "<{}> needs exactly one <{}>",
+                self.name,
+                name
+            );
+            found[0]
+        }
+    }
+
+    pub fn parse(xml: &str) -> Node {
+        let mut reader = Reader::from_str(xml);
+        let mut stack = vec![Node::default()];
+        loop {
+            match reader.read_event().unwrap() {
+                Event::Start(start) => stack.push(Node::new(&start)),
+                Event::Empty(start) => stack.last_mut().unwrap().children.push(Node::new(&start)),
+                Event::Text(text) => stack
+                    .last_mut()
+                    .unwrap()
+                    .text
+                    .push_str(&text.unescape().unwrap()),
+                Event::End(_) => {
+                    let node = stack.pop().unwrap();
+                    stack.last_mut().unwrap().children.push(node);
+                }
+                Event::Eof => break,
+                _ => {}
+            }
+        }
+        assert_eq!(stack.len(), 1, "Unclosed elements");
+        stack.pop().unwrap().children.pop().unwrap()
+    }
+
+    pub fn post(title: &str, slug: &str, updated: Option<NaiveDate>) -> Post {
+        let mut frontmatter = FrontMatter::default();
+        frontmatter.title = title.to_string();
+        frontmatter.slug = slug.to_string();
+        frontmatter.published = Timestamp::Date(NaiveDate::from_ymd_opt(2023, 4, 5).unwrap());
+        frontmatter.updated = updated.map(Timestamp::Date);
+        frontmatter.tags = vec!["rust".to_string(), "c&c".to_string()];
+        frontmatter.visibility = Visibility::Public;
+        Post {
+            frontmatter,
+            rendered: "<p>Fish &amp; chips &lt;3</p><p>More <em>words</em>.</p>".to_string(),
+            readtime: 1,
+            bundle: None,
+            modified: None,
+            toc: Vec::new(),
+            timezone: Tz::UTC,
+            assets: Default::default(),
+        }
+    }
+
+    fn links(page: &Page) -> Vec<(String, String)> {
+        let body = page.body(Encoding::Identity);
+        parse(std::str::from_utf8(&body).unwrap())
+            .all("link")
+            .iter()
+            .filter(|l| l.attributes["rel"] != "alternate")
+            .map(|l| {
+                let href = l.attributes["href"].trim_start_matches("https://whydoesntmycode.work");
+                (l.attributes["rel"].clone(), href.to_string())
+            })
+            .collect()
+    }
+
+    fn slugs(page: &Page) -> Vec<String> {
+        let body = page.body(Encoding::Identity);
+        parse(std::str::from_utf8(&body).unwrap())
+            .all("entry")
+            .iter()
+            .map(|e| e.one("id").text.rsplit('/').next().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn old_posts_are_archived() {
+        let posts: Vec<Post> = (1..=5)
+            .rev()
+            .map(|n| post(&n.to_string(), &n.to_string(), None))
+            .collect();
+        let feeds = Feeds::new(
+            &Channel::site(),
+            &posts.iter().collect::<Vec<_>>(),
+            2,
+            Utc::now(),
+        );
+
+        assert_eq!(slugs(&feeds.atom), ["5", "4"]);
+        assert_eq!(
+            links(&feeds.atom),
+            [
+                ("self".into(), "/feeds/atom.xml".into()),
+                ("prev-archive".into(), "/feeds/archive/2".into())
+            ]
+        );
+
+        let oldest = feeds.archive(1).unwrap();
+        assert_eq!(slugs(oldest), ["2", "1"]);
+        assert_eq!(
+            links(oldest),
+            [
+                ("self".into(), "/feeds/archive/1".into()),
+                ("current".into(), "/feeds/atom.xml".into()),
+                ("next-archive".into(), "/feeds/archive/2".into())
+            ]
+        );
+        let body = oldest.body(Encoding::Identity);
+        assert!(std::str::from_utf8(&body)
+            .unwrap()
+            .contains("<fh:archive/>"));
+
+        let newest = feeds.archive(2).unwrap();
+        assert_eq!(slugs(newest), ["4", "3"]);
+        assert_eq!(
+            links(newest),
+            [
+                ("self".into(), "/feeds/archive/2".into()),
+                ("current".into(), "/feeds/atom.xml".into()),
+                ("prev-archive".into(), "/feeds/archive/1".into())
+            ]
+        );
+
+        assert!(feeds.archive(0).is_none());
+        assert!(feeds.archive(3).is_none());
+    }
+
+    #[test]
+    fn short_feeds_have_no_archives() {
+        let posts = [post("1", "1", None), post("2", "2", None)];
+        let feeds = Feeds::new(
+            &Channel::site(),
+            &posts.iter().collect::<Vec<_>>(),
+            2,
+            Utc::now(),
+        );
+        assert_eq!(slugs(&feeds.atom), ["1", "2"]);
+        assert_eq!(
+            links(&feeds.atom),
+            [("self".into(), "/feeds/atom.xml".into())]
+        );
+        assert!(feeds.archive(1).is_none());
+    }
+
+    #[test]
+    fn tags_are_escaped_in_urls() {
+        let channel = Channel::tag("Raspberry Pi & c&c");
+        assert_eq!(channel.path, "/tag/Raspberry%20Pi%20%26%20c%26c");
+        assert_eq!(
+            channel.title,
+            "Why Doesn't My Code Work? #Raspberry Pi & c&c"
+        );
+
+        let posts = [post("1", "1", None)];
+        let feeds = Feeds::new(&channel, &posts.iter().collect::<Vec<_>>(), 2, Utc::now());
+        let body = feeds.atom.body(Encoding::Identity);
+        let atom = parse(std::str::from_utf8(&body).unwrap());
+        assert_eq!(
+            atom.one("id").text,
+            "https://whydoesntmycode.work/tag/Raspberry%20Pi%20%26%20c%26c/atom.xml"
+        );
+        let json: serde_json::Value =
+            serde_json::from_slice(&feeds.json.body(Encoding::Identity)).unwrap();
+        assert_eq!(
+            json["feed_url"],
+            "https://whydoesntmycode.work/tag/Raspberry%20Pi%20%26%20c%26c/feed.json"
+        );
+        assert_eq!(
+            json["home_page_url"],
+            "https://whydoesntmycode.work/tag/Raspberry%20Pi%20%26%20c%26c"
+        );
+    }
+
+    #[test]
+    fn every_format_holds_the_newest_posts() {
+        let posts: Vec<Post> = (1..=3)
+            .rev()
+            .map(|n| post(&n.to_string(), &n.to_string(), None))
+            .collect();
+        let feeds = Feeds::new(
+            &Channel::site(),
+            &posts.iter().collect::<Vec<_>>(),
+            2,
+            Utc::now(),
+        );
+        assert_eq!(slugs(&feeds.atom), ["3", "2"]);
+        let body = feeds.rss.body(Encoding::Identity);
+        assert_eq!(
+            parse(std::str::from_utf8(&body).unwrap())
+                .one("channel")
+                .all("item")
+                .len(),
+            2
+        );
+        let json: serde_json::Value =
+            serde_json::from_slice(&feeds.json.body(Encoding::Identity)).unwrap();
+        assert_eq!(json["items"].as_array().unwrap().len(), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "at least one post")]
+    fn feeds_hold_at_least_one_post() {
+        Feeds::new(&Channel::site(), &[], 0, Utc::now());
+    }
+}
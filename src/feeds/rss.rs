@@ -0,0 +1,141 @@
+use chrono::{DateTime, Utc};
+use quick_xml::events::{BytesDecl, BytesText, Event};
+use quick_xml::{Result, Writer};
+
+use super::{
+    summary, text, updated, xml, Channel, ATOM_NS, AUTHOR_EMAIL, AUTHOR_NAME, STYLESHEET_PI,
+};
+use crate::types::Post;
+
+const CONTENT_NS: &str = "http://purl.org/rss/1.0/modules/content/";
+const DC_NS: &str = "http://purl.org/dc/elements/1.1/";
+
+pub const CONTENT_TYPE: &str = "application/rss+xml";
+
+/// Build the RSS feed for the given posts, which should be newest first.
+pub fn feed(channel: &Channel, posts: &[&Post], built: DateTime<Utc>) -> String {
+    xml(|writer| write_feed(writer, channel, posts, built))
+}
+
+fn write_feed(
+    writer: &mut Writer<Vec<u8>>,
+    channel: &Channel,
+    posts: &[&Post],
+    built: DateTime<Utc>,
+) -> Result<()> {
+    // RSS wants an "email (name)" pair wherever a person goes.
+    let author = format!("{} ({})", AUTHOR_EMAIL, AUTHOR_NAME);
+    writer.write_event(Event::Decl(BytesDecl::new("1.0", Some("UTF-8"), None)))?;
+    writer.write_event(Event::PI(BytesText::from_escaped(STYLESHEET_PI)))?;
+    writer
+        .create_element("rss")
+        .with_attribute(("version", "2.0"))
+        .with_attribute(("xmlns:atom", ATOM_NS))
+        .with_attribute(("xmlns:content", CONTENT_NS))
+        .with_attribute(("xmlns:dc", DC_NS))
+        .write_inner_content(|writer| {
+            writer
+                .create_element("channel")
+                .write_inner_content(|writer| {
+                    text(writer, "title", &channel.title)?;
+                    text(writer, "link", &channel.home_page_url())?;
+                    text(writer, "description", &channel.title)?;
+                    text(writer, "language", "en")?;
+                    text(writer, "managingEditor", &author)?;
+                    text(writer, "lastBuildDate", &updated(posts, built).to_rfc2822())?;
+                    writer
+                        .create_element("atom:link")
+                        .with_attribute(("href", channel.url("rss.xml").as_str()))
+                        .with_attribute(("rel", "self"))
+                        .with_attribute(("type", CONTENT_TYPE))
+                        .write_empty()?;
+                    for post in posts.iter() {
+                        item(writer, post, &author)?;
+                    }
+                    Ok(())
+                })?;
+            Ok(())
+        })?;
+    Ok(())
+}
+
+fn item(writer: &mut Writer<Vec<u8>>, post: &Post, author: &str) -> Result<()> {
+    let url = post.get_full_url();
+    writer
+        .create_element("item")
+        .write_inner_content(|writer| {
+            text(writer, "title", &post.frontmatter.title)?;
+            text(writer, "link", &url)?;
+            writer
+                .create_element("guid")
+                .with_attribute(("isPermaLink", "true"))
+                .write_text_content(BytesText::new(&url))?;
+            text(writer, "pubDate", &post.published_at().to_rfc2822())?;
+            // <author> has to be an email address, and we only have one for
+            // the site's own author. Anyone else gets a Dublin Core name.
+            match &post.frontmatter.author {
+                Some(name) => text(writer, "dc:creator", name)?,
+                None => text(writer, "author", author)?,
+            }
+            for tag in post.frontmatter.tags.iter() {
+                text(writer, "category", tag)?;
+            }
+            text(writer, "description", &summary(post))?;
+            text(writer, "content:encoded", &post.rendered)?;
+            Ok(())
+        })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::feeds::tests::{parse, post};
+    use chrono::NaiveDate;
+
+    #[test]
+    fn follows_the_spec() {
+        let posts = [
+            post("Tom & Jerry <3", "tom-and-jerry", None),
+            post("Second", "second", NaiveDate::from_ymd_opt(2023, 5, 1)),
+        ];
+        let rss = parse(&feed(
+            &Channel::site(),
+            &posts.iter().collect::<Vec<_>>(),
+            Utc::now(),
+        ));
+
+        assert_eq!(rss.name, "rss");
+        assert_eq!(rss.attributes["version"], "2.0");
+        let channel = rss.one("channel");
+        channel.one("title");
+        channel.one("link");
+        channel.one("description");
+        let self_link = channel.one("atom:link");
+        assert_eq!(self_link.attributes["rel"], "self");
+        assert_eq!(
+            self_link.attributes["href"],
+            "https://whydoesntmycode.work/feeds/rss.xml"
+        );
+
+        let items = channel.all("item");
+        assert_eq!(items.len(), 2);
+        for item in items.iter() {
+            DateTime::parse_from_rfc2822(&item.one("pubDate").text).unwrap();
+            let guid = item.one("guid");
+            assert_eq!(guid.attributes["isPermaLink"], "true");
+            assert_eq!(guid.text, item.one("link").text);
+            item.one("description");
+        }
+
+        let first = items[0];
+        assert_eq!(first.one("title").text, "Tom & Jerry <3");
+        assert_eq!(first.one("pubDate").text, "Wed, 05 Apr 2023 00:00:00 +0000");
+        assert_eq!(
+            first.one("content:encoded").text,
+            "<p>Fish &amp; chips &lt;3</p><p>More <em>words</em>.</p>"
+        );
+        let categories: Vec<_> = first.all("category").iter().map(|c| &c.text).collect();
+        assert_eq!(categories, ["rust", "c&c"]);
+    }
+}
@@ -0,0 +1,632 @@
+use chrono::{DateTime, Duration, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde::de::{self, IgnoredAny, Visitor};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::types::PostParseError;
+
+/// The formats frontmatter can be written in, each with its own way of
+/// marking where the frontmatter starts.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FrontMatterFormat {
+    /// Between `---` fences, the way most of our posts are written.
+    Yaml,
+    /// Between `+++` fences, as Hugo does it.
+    Toml,
+    /// A single JSON object right at the start of the post.
+    Json,
+    /// `#+KEYWORD:` lines in an org file.
+    Org,
+}
+
+impl fmt::Display for FrontMatterFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrontMatterFormat::Yaml => write!(f, "YAML"),
+            FrontMatterFormat::Toml => write!(f, "TOML"),
+            FrontMatterFormat::Json => write!(f, "JSON"),
+            FrontMatterFormat::Org => write!(f, "Org"),
+        }
+    }
+}
+
+/// When something happened to a post. Either a full RFC 3339 timestamp, a
+/// date and time without an offset, or just a date. Anything without an
+/// offset is taken to be in the site's timezone, and a plain date means
+/// midnight there.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Timestamp {
+    Date(NaiveDate),
+    Local(NaiveDateTime),
+    DateTime(DateTime<FixedOffset>),
+}
+
+impl Timestamp {
+    /// The day it happened on, as whoever wrote it down saw it.
+    pub fn date(&self) -> NaiveDate {
+        match self {
+            Timestamp::Date(date) => *date,
+            Timestamp::Local(datetime) => datetime.date(),
+            Timestamp::DateTime(datetime) => datetime.date_naive(),
+        }
+    }
+
+    /// The exact instant, with anything lacking an offset taken to be in
+    /// `timezone`.
+    pub fn in_timezone(&self, timezone: Tz) -> DateTime<Utc> {
+        match self {
+            Timestamp::Date(date) => local(timezone, date.and_time(NaiveTime::MIN)),
+            Timestamp::Local(datetime) => local(timezone, *datetime),
+            Timestamp::DateTime(datetime) => datetime.with_timezone(&Utc),
+        }
+    }
+}
+
+/// A wall clock time in `timezone`. A time skipped by a DST change is moved
+/// on to whenever the clocks next show an hour that exists.
+fn local(timezone: Tz, datetime: NaiveDateTime) -> DateTime<Utc> {
+    (0..24)
+        .find_map(|hours| {
+            timezone
+                .from_local_datetime(&(datetime + Duration::hours(hours)))
+                .earliest()
+        })
+        .map_or_else(
+            || DateTime::from_utc(datetime, Utc),
+            |start| start.with_timezone(&Utc),
+        )
+}
+
+impl Default for Timestamp {
+    fn default() -> Self {
+        Timestamp::Date(NaiveDate::default())
+    }
+}
+
+impl FromStr for Timestamp {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if let Ok(datetime) = DateTime::parse_from_rfc3339(value) {
+            return Ok(Timestamp::DateTime(datetime));
+        }
+        if let Ok(datetime) = NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S%.f") {
+            return Ok(Timestamp::Local(datetime));
+        }
+        NaiveDate::parse_from_str(value, "%Y-%m-%d")
+            .map(Timestamp::Date)
+            .map_err(|_| {
+                format!(
+                    "expected a date like 2023-04-01, or a time like 2023-04-01T09:30:00 with an optional offset, got {:?}",
+                    value
+                )
+            })
+    }
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Timestamp::Date(date) => write!(f, "{}", date.format("%Y-%m-%d")),
+            Timestamp::Local(datetime) => write!(f, "{}", datetime.format("%Y-%m-%dT%H:%M:%S%.f")),
+            Timestamp::DateTime(datetime) => write!(f, "{}", datetime.to_rfc3339()),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Timestamp {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct TimestampVisitor;
+
+        impl<'de> Visitor<'de> for TimestampVisitor {
+            type Value = Timestamp;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a date, a date and time, or an RFC 3339 timestamp")
+            }
+
+            // Parsing in here, rather than after the fact, lets the format
+            // say where in the file the bad value was.
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<Timestamp, E> {
+                value.parse().map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_str(TimestampVisitor)
+    }
+}
+
+/// Who gets to see a post.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Visibility {
+    /// On the blog, in the feeds, the lot.
+    Public,
+    /// Served to anyone with the link, but not listed anywhere and kept out
+    /// of search engines. For showing a post to reviewers before it's out.
+    Unlisted,
+    /// Not served at all outside of debug mode.
+    #[default]
+    Private,
+}
+
+impl<'de> Deserialize<'de> for Visibility {
+    /// Takes one of the names, or a bool since that's what `public` used to be.
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct VisibilityVisitor;
+
+        impl<'de> Visitor<'de> for VisibilityVisitor {
+            type Value = Visibility;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "one of public, unlisted or private, or a bool")
+            }
+
+            fn visit_bool<E: de::Error>(self, public: bool) -> Result<Visibility, E> {
+                Ok(match public {
+                    true => Visibility::Public,
+                    false => Visibility::Private,
+                })
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<Visibility, E> {
+                match value {
+                    "public" => Ok(Visibility::Public),
+                    "unlisted" => Ok(Visibility::Unlisted),
+                    "private" => Ok(Visibility::Private),
+                    _ => Err(E::unknown_variant(
+                        value,
+                        &["public", "unlisted", "private"],
+                    )),
+                }
+            }
+        }
+
+        deserializer.deserialize_any(VisibilityVisitor)
+    }
+}
+
+#[derive(Deserialize, PartialEq, Clone, Default)]
+pub struct FrontMatter {
+    pub title: String,
+    pub slug: String,
+    pub published: Timestamp,
+    pub updated: Option<Timestamp>,
+    pub tags: Vec<String>,
+    /// Written as `visibility`, or as `public: true/false` in older posts.
+    #[serde(alias = "public")]
+    pub visibility: Visibility,
+    /// A line or two about the post, for link previews, listings and feeds.
+    pub description: Option<String>,
+    /// An image for link previews. Relative paths are relative to the post.
+    pub cover_image: Option<String>,
+    /// Where the post first appeared, if it wasn't here.
+    pub canonical_url: Option<String>,
+    /// The language the post is written in, when it isn't English.
+    pub lang: Option<String>,
+    /// Who wrote the post, when it wasn't Freyja.
+    pub author: Option<String>,
+    /// Whether to put a table of contents at the top of the post.
+    #[serde(default)]
+    pub toc: bool,
+    /// Anything we don't recognise, kept only so we can warn about it.
+    #[serde(flatten)]
+    unknown: BTreeMap<String, IgnoredAny>,
+}
+
+impl FrontMatter {
+    /// Keys in the frontmatter that don't mean anything to us, in order.
+    pub fn unknown_keys(&self) -> Vec<String> {
+        self.unknown.keys().cloned().collect()
+    }
+
+    /// Parse the frontmatter off the top of a post, working out which format
+    /// it's in from how it starts.
+    /// Hands back the rest of the post too, that's the bit that wants
+    /// rendering.
+    pub fn new(content: &str) -> Result<(FrontMatter, &str), PostParseError> {
+        let content = content.strip_prefix('\u{feff}').unwrap_or(content);
+        let first_line = content.lines().next().unwrap_or_default().trim_end();
+        if first_line == "---" {
+            Self::from_yaml(content)
+        } else if first_line == "+++" {
+            Self::from_toml(content)
+        } else if content.starts_with('{') {
+            Self::from_json(content)
+        } else {
+            Err(PostParseError::NoFrontmatter)
+        }
+    }
+
+    fn from_yaml(content: &str) -> Result<(FrontMatter, &str), PostParseError> {
+        let (yaml, body) = split(content, "---", FrontMatterFormat::Yaml)?;
+        let frontmatter = serde_yaml::from_str(yaml).map_err(|e| {
+            let location = e.location();
+            // serde_yaml tacks its own position onto the message, but that's
+            // relative to the yaml rather than the file. We report our own.
+            let message = e.to_string();
+            let message = match &location {
+                Some(_) => message
+                    .rsplit_once(" at line ")
+                    .map(|(m, _)| m.to_string())
+                    .unwrap_or(message),
+                None => message,
+            };
+            PostParseError::FrontmatterError {
+                format: FrontMatterFormat::Yaml,
+                message,
+                // The yaml starts on the line after the opening fence.
+                line: location.as_ref().map(|l| l.line() + 1),
+                column: location.as_ref().map(|l| l.column()),
+            }
+        })?;
+        Ok((frontmatter, body))
+    }
+
+    fn from_toml(content: &str) -> Result<(FrontMatter, &str), PostParseError> {
+        let (toml, body) = split(content, "+++", FrontMatterFormat::Toml)?;
+        let error = |e: toml::de::Error| {
+            // Like the yaml, the toml starts on the line after the fence.
+            let position = e.span().map(|span| line_and_column(toml, span.start));
+            PostParseError::FrontmatterError {
+                format: FrontMatterFormat::Toml,
+                message: e.message().to_string(),
+                line: position.map(|(line, _)| line + 1),
+                column: position.map(|(_, column)| column),
+            }
+        };
+        let mut table: toml::Table = toml::from_str(toml).map_err(error)?;
+        // Hugo writes its dates as bare toml datetimes, which only deserialize
+        // into toml's own type. Hand them over as text, the same as a quoted
+        // date in yaml or json, and let `Timestamp` parse them.
+        for (_, value) in table.iter_mut() {
+            if let toml::Value::Datetime(date) = value {
+                *value = toml::Value::String(date.to_string());
+            }
+        }
+        let frontmatter = table.try_into().map_err(error)?;
+        Ok((frontmatter, body))
+    }
+
+    fn from_json(content: &str) -> Result<(FrontMatter, &str), PostParseError> {
+        let mut stream = serde_json::Deserializer::from_str(content).into_iter::<FrontMatter>();
+        let frontmatter = match stream.next() {
+            Some(Ok(frontmatter)) => frontmatter,
+            Some(Err(e)) if e.is_eof() => {
+                return Err(PostParseError::UnterminatedFrontmatter {
+                    format: FrontMatterFormat::Json,
+                })
+            }
+            Some(Err(e)) => {
+                // The message has the position baked in, we report it separately.
+                let message = e.to_string();
+                let message = message
+                    .rsplit_once(" at line ")
+                    .map(|(m, _)| m.to_string())
+                    .unwrap_or(message);
+                return Err(PostParseError::FrontmatterError {
+                    format: FrontMatterFormat::Json,
+                    message,
+                    line: Some(e.line()),
+                    column: Some(e.column()),
+                });
+            }
+            None => return Err(PostParseError::NoFrontmatter),
+        };
+        // Everything after the closing brace is the post, less the rest of the
+        // line the brace was on.
+        let rest = &content[stream.byte_offset()..];
+        let body = match rest.split_once('\n') {
+            Some((line, body)) if line.trim().is_empty() => body,
+            _ => rest,
+        };
+        Ok((frontmatter, body))
+    }
+}
+
+/// Split a post into its frontmatter and its body.
+/// The fences have to sit on lines of their own, and the opening one has to be
+/// the first line of the file, so a fence anywhere else is left well alone.
+/// CRLF line endings are fine.
+fn split<'a>(
+    content: &'a str,
+    fence: &str,
+    format: FrontMatterFormat,
+) -> Result<(&'a str, &'a str), PostParseError> {
+    let is_fence = |line: &str| line.trim_end() == fence;
+    let mut lines = content.split_inclusive('\n');
+    let start = match lines.next() {
+        Some(first) if is_fence(first) => first.len(),
+        _ => return Err(PostParseError::NoFrontmatter),
+    };
+    let mut end = start;
+    for line in lines {
+        if is_fence(line) {
+            return Ok((&content[start..end], &content[end + line.len()..]));
+        }
+        end += line.len();
+    }
+    Err(PostParseError::UnterminatedFrontmatter { format })
+}
+
+/// Turn a byte offset into a 1 based line and column.
+fn line_and_column(text: &str, offset: usize) -> (usize, usize) {
+    let before = &text[..offset];
+    let line = before.matches('\n').count() + 1;
+    let column = before.len() - before.rfind('\n').map(|i| i + 1).unwrap_or(0) + 1;
+    (line, column)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    const YAML: &str =
+        "title: A Post\nslug: a-post\npublished: 2023-04-01\ntags: [a, b]\npublic: true\n";
+
+    #[test]
+    fn splits_frontmatter_from_body() {
+        let post = format!("---\n{}---\n# Hello\n", YAML);
+        assert_eq!(
+            split(&post, "---", FrontMatterFormat::Yaml).unwrap(),
+            (YAML, "# Hello\n")
+        );
+    }
+
+    #[test]
+    fn dashes_inside_yaml_are_not_fences() {
+        let post = "---\ntitle: \"this --- that\"\nslug: s\npublished: 2023-04-01\ntags: []\npublic: true\n---\nbody";
+        let (fm, body) = FrontMatter::new(post).unwrap();
+        assert_eq!(fm.title, "this --- that");
+        assert_eq!(body, "body");
+    }
+
+    #[test]
+    fn dashes_mid_line_in_the_body_are_left_alone() {
+        let post = format!("---\n{}---\nwait---what\n\n---\nmore", YAML);
+        let (_, body) = FrontMatter::new(&post).unwrap();
+        assert_eq!(body, "wait---what\n\n---\nmore");
+    }
+
+    #[test]
+    fn handles_crlf_and_bom() {
+        let post = format!(
+            "\u{feff}---\r\n{}---\r\nbody\r\n",
+            YAML.replace('\n', "\r\n")
+        );
+        let (fm, body) = FrontMatter::new(&post).unwrap();
+        assert_eq!(fm.slug, "a-post");
+        assert_eq!(body, "body\r\n");
+    }
+
+    #[test]
+    fn frontmatter_has_to_come_first() {
+        let post = format!("# Title\n---\n{}---\n", YAML);
+        assert!(matches!(
+            FrontMatter::new(&post),
+            Err(PostParseError::NoFrontmatter)
+        ));
+    }
+
+    #[test]
+    fn unterminated_frontmatter() {
+        let post = format!("---\n{}", YAML);
+        assert!(matches!(
+            FrontMatter::new(&post),
+            Err(PostParseError::UnterminatedFrontmatter { .. })
+        ));
+    }
+
+    #[test]
+    fn yaml_errors_point_at_the_file_line() {
+        let post = "---\ntitle: x\nslug: y\npublished: notadate\ntags: []\npublic: true\n---\n";
+        match FrontMatter::new(post) {
+            Err(PostParseError::FrontmatterError { message, line, .. }) => {
+                assert_eq!(line, Some(4));
+                assert!(!message.contains(" at line "));
+            }
+            _ => panic!("expected a frontmatter error"),
+        }
+    }
+
+    #[test]
+    fn toml_frontmatter() {
+        let post = "+++\ntitle = \"From Hugo\"\nslug = \"hugo\"\npublished = 2023-04-01\nupdated = 2023-04-02\ntags = [\"a\"]\npublic = true\n+++\nbody";
+        let (fm, body) = FrontMatter::new(post).unwrap();
+        assert_eq!(fm.title, "From Hugo");
+        assert_eq!(fm.published.to_string(), "2023-04-01");
+        assert_eq!(fm.updated.unwrap().to_string(), "2023-04-02");
+        assert_eq!(body, "body");
+
+        // The way Hugo writes dates out itself.
+        let post = "+++\ntitle = \"x\"\nslug = \"y\"\npublished = 2023-06-01T09:30:00Z\nupdated = 2023-06-02T10:00:00+02:00\ntags = []\npublic = true\n+++\n";
+        let (fm, _) = FrontMatter::new(post).unwrap();
+        assert_eq!(fm.published.to_string(), "2023-06-01T09:30:00+00:00");
+        assert_eq!(fm.updated.unwrap().date().to_string(), "2023-06-02");
+    }
+
+    #[test]
+    fn json_frontmatter() {
+        let post = "{\n  \"title\": \"Tooling\",\n  \"slug\": \"tooling\",\n  \"published\": \"2023-04-01\",\n  \"tags\": [],\n  \"public\": true\n}\n# Body {with braces}\n";
+        let (fm, body) = FrontMatter::new(post).unwrap();
+        assert_eq!(fm.slug, "tooling");
+        assert_eq!(body, "# Body {with braces}\n");
+    }
+
+    #[test]
+    fn errors_are_reported_per_format() {
+        let toml = "+++\ntitle = \"x\"\nslug = = \"y\"\n+++\n";
+        match FrontMatter::new(toml) {
+            Err(PostParseError::FrontmatterError {
+                format: FrontMatterFormat::Toml,
+                line,
+                column,
+                ..
+            }) => {
+                assert_eq!(line, Some(3));
+                assert_eq!(column, Some(8));
+            }
+            _ => panic!("expected a toml error"),
+        }
+
+        let json = "{\n  \"title\": \"x\",\n  \"slug\": 4\n}\n";
+        match FrontMatter::new(json) {
+            Err(PostParseError::FrontmatterError {
+                format: FrontMatterFormat::Json,
+                line,
+                ..
+            }) => assert_eq!(line, Some(3)),
+            _ => panic!("expected a json error"),
+        }
+
+        assert!(matches!(
+            FrontMatter::new("+++\ntitle = \"x\"\n"),
+            Err(PostParseError::UnterminatedFrontmatter {
+                format: FrontMatterFormat::Toml
+            })
+        ));
+        assert!(matches!(
+            FrontMatter::new("{\"title\": \"x\""),
+            Err(PostParseError::UnterminatedFrontmatter {
+                format: FrontMatterFormat::Json
+            })
+        ));
+    }
+
+    #[test]
+    fn optional_fields() {
+        let post = format!(
+            "---\n{}description: Short\nlang: de\nauthor: Guest\ntoc: true\n---\n",
+            YAML
+        );
+        let (fm, _) = FrontMatter::new(&post).unwrap();
+        assert_eq!(fm.description.as_deref(), Some("Short"));
+        assert_eq!(fm.lang.as_deref(), Some("de"));
+        assert_eq!(fm.author.as_deref(), Some("Guest"));
+        assert_eq!(fm.cover_image, None);
+        assert!(fm.toc);
+        assert!(fm.unknown_keys().is_empty());
+    }
+
+    #[test]
+    fn unknown_keys_are_kept_track_of() {
+        let post = format!("---\n{}started: 2023-07-28\nsulg: typo\n---\n", YAML);
+        let (fm, _) = FrontMatter::new(&post).unwrap();
+        assert_eq!(fm.unknown_keys(), vec!["started", "sulg"]);
+
+        let toml = "+++\ntitle = \"x\"\nslug = \"y\"\npublished = 2023-04-01\ntags = []\npublic = true\ndraft = true\n+++\n";
+        let (fm, _) = FrontMatter::new(toml).unwrap();
+        assert_eq!(fm.unknown_keys(), vec!["draft"]);
+    }
+
+    #[test]
+    fn timestamps() {
+        let published = |value: &str| {
+            let post = format!(
+                "---\ntitle: x\nslug: y\npublished: {}\ntags: []\npublic: true\n---\n",
+                value
+            );
+            FrontMatter::new(&post).map(|(fm, _)| fm.published)
+        };
+        let london: Tz = "Europe/London".parse().unwrap();
+        let utc = |s: &str| DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc);
+
+        let date = published("2023-06-01").unwrap();
+        assert_eq!(date.in_timezone(Tz::UTC), utc("2023-06-01T00:00:00Z"));
+        assert_eq!(date.in_timezone(london), utc("2023-05-31T23:00:00Z"));
+
+        // A timestamp says where it is, the site's timezone doesn't matter.
+        let datetime = published("2023-06-01T09:30:00+02:00").unwrap();
+        assert_eq!(datetime.in_timezone(london), utc("2023-06-01T07:30:00Z"));
+        assert_eq!(datetime.date().to_string(), "2023-06-01");
+        assert_eq!(datetime.to_string(), "2023-06-01T09:30:00+02:00");
+
+        // São Paulo skipped midnight when DST started in 2018.
+        let sao_paulo: Tz = "America/Sao_Paulo".parse().unwrap();
+        assert_eq!(
+            published("2018-11-04").unwrap().in_timezone(sao_paulo),
+            utc("2018-11-04T01:00:00-02:00")
+        );
+
+        // Without an offset it's the site's wall clock, DST and all.
+        let local = published("2023-06-01T09:30:00").unwrap();
+        assert_eq!(local.in_timezone(london), utc("2023-06-01T08:30:00Z"));
+        assert_eq!(
+            published("2023-03-26T01:30:00")
+                .unwrap()
+                .in_timezone(london),
+            utc("2023-03-26T02:30:00+01:00")
+        );
+        assert!(published("yesterday").is_err());
+
+        let toml = "+++\ntitle = \"x\"\nslug = \"y\"\npublished = 2023-06-01T09:30:00Z\ntags = []\npublic = true\n+++\n";
+        let (fm, _) = FrontMatter::new(toml).unwrap();
+        assert_eq!(
+            fm.published.in_timezone(london),
+            utc("2023-06-01T09:30:00Z")
+        );
+    }
+
+    #[test]
+    fn visibility() {
+        let visibility = |line: &str| {
+            let post = format!(
+                "---\ntitle: x\nslug: y\npublished: 2023-04-01\ntags: []\n{}\n---\n",
+                line
+            );
+            FrontMatter::new(&post).map(|(fm, _)| fm.visibility)
+        };
+        assert_eq!(
+            visibility("visibility: unlisted").unwrap(),
+            Visibility::Unlisted
+        );
+        assert_eq!(
+            visibility("visibility: private").unwrap(),
+            Visibility::Private
+        );
+        assert_eq!(visibility("public: true").unwrap(), Visibility::Public);
+        assert_eq!(visibility("public: false").unwrap(), Visibility::Private);
+        assert!(visibility("visibility: secret").is_err());
+        assert!(visibility("").is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn never_panics(content in "\\PC*") {
+            let _ = FrontMatter::new(&content);
+        }
+
+        #[test]
+        fn never_panics_on_fenced_garbage(yaml in "[-:\\[\\]{}\"' \\r\\na-z0-9]*", body in "\\PC*") {
+            let _ = FrontMatter::new(&format!("---\n{}\n---\n{}", yaml, body));
+        }
+
+        #[test]
+        fn never_panics_on_other_formats(matter in "[-+=:\\[\\]{}\"' \\r\\na-z0-9]*", body in "\\PC*") {
+            let _ = FrontMatter::new(&format!("+++\n{}\n+++\n{}", matter, body));
+            let _ = FrontMatter::new(&format!("{{{}}}\n{}", matter, body));
+        }
+
+        #[test]
+        fn body_comes_through_untouched(body in "\\PC*") {
+            let post = format!("---\n{}---\n{}", YAML, body);
+            let (_, rest) = FrontMatter::new(&post).unwrap();
+            prop_assert_eq!(rest, body.as_str());
+        }
+
+        #[test]
+        fn titles_round_trip(title in "[-a-zA-Z0-9 &<>:'\"]{1,40}") {
+            let quoted = serde_yaml::to_string(&title).unwrap();
+            let post = format!(
+                "---\ntitle: {}slug: s\npublished: 2023-04-01\ntags: []\npublic: true\n---\n",
+                quoted
+            );
+            let (fm, _) = FrontMatter::new(&post).unwrap();
+            prop_assert_eq!(fm.title, title);
+        }
+    }
+}
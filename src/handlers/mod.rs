@@ -1,18 +1,25 @@
-use crate::tmpl::{
-    render_about, render_blogpost, render_feeds, render_home, render_postlist,
-    render_tagged_post_list,
-};
-use crate::types::State;
+use crate::feeds::SITE_URL;
+use crate::preview;
+use crate::sitemap;
+use crate::tmpl::{render_about, render_blogpost, render_feeds, render_home, render_unknown_tag};
+use crate::types::{Post, State, Visibility};
 
-use axum::response::IntoResponse;
+use axum::body::{self, Body};
+use axum::extract::Query;
+use axum::response::{IntoResponse, Response};
 use axum::Json;
-use axum::{extract::Path, headers::ContentType, http::StatusCode, Extension, TypedHeader};
+use axum::{extract::Path, http::Request, http::StatusCode, Extension};
+use chrono::{DateTime, Duration, Utc};
+use hyper::header::{HeaderValue, CACHE_CONTROL, CONTENT_TYPE, REFERRER_POLICY};
 use hyper::HeaderMap;
 use lazy_static::lazy_static;
 use maud::{html, Markup};
-use prometheus::{opts, register_int_counter_vec, Encoder, IntCounterVec, TextEncoder};
-use serde::Deserialize;
+use prometheus::{opts, register_int_counter_vec, IntCounterVec};
+use serde::{Deserialize, Serialize};
+use std::path::{Component, PathBuf};
 use std::sync::Arc;
+use tower::ServiceExt;
+use tower_http::services::ServeFile;
 
 lazy_static! {
     static ref BLOGPOST_HIT_COUNTER: IntCounterVec = register_int_counter_vec!(
@@ -31,33 +38,153 @@ pub struct AdminToken {
     admin_token: String,
 }
 
-pub async fn list_posts(Extension(state): Extension<Arc<State>>) -> Markup {
+pub async fn list_posts(Extension(state): Extension<Arc<State>>, headers: HeaderMap) -> Response {
     PAGE_HIT_COUNTER.with_label_values(&["post_list"]).inc();
-    render_postlist(state).await
+    state.content().post_list.respond(StatusCode::OK, &headers)
 }
 
 pub async fn blogpost(
     Path(slug): Path<String>,
     Extension(state): Extension<Arc<State>>,
-) -> (StatusCode, Markup) {
-    for post in state.posts.read().await.iter() {
-        if post.frontmatter.slug == slug {
+    headers: HeaderMap,
+) -> Response {
+    let content = state.content();
+    match (content.post(&slug), content.post_page(&slug)) {
+        (Some(post), Some(page)) => {
             BLOGPOST_HIT_COUNTER
-                .with_label_values(&[post.frontmatter.title.clone().as_str()])
+                .with_label_values(&[post.frontmatter.title.as_str()])
                 .inc();
-            return (StatusCode::OK, render_blogpost(post).await);
+            let mut response = page.respond(StatusCode::OK, &headers);
+            if post.frontmatter.visibility != Visibility::Public {
+                response
+                    .headers_mut()
+                    .insert("x-robots-tag", HeaderValue::from_static("noindex"));
+            }
+            response
         }
+        _ => handle_404().await.into_response(),
     }
-    handle_404().await
+}
+
+#[derive(Deserialize)]
+pub struct PreviewToken {
+    #[serde(default)]
+    token: String,
+}
+
+/// Show a post, drafts included, to whoever has a preview link for it.
+/// Anything wrong with the link is a plain 404, so it gives nothing away
+/// about which drafts exist.
+pub async fn preview(
+    Path(slug): Path<String>,
+    Query(query): Query<PreviewToken>,
+    Extension(state): Extension<Arc<State>>,
+) -> Response {
+    let content = state.content();
+    let post = content
+        .preview(&slug)
+        .filter(|_| state.preview_key.verify(&slug, &query.token, Utc::now()));
+    let Some(post) = post else {
+        return handle_404().await.into_response();
+    };
+    PAGE_HIT_COUNTER.with_label_values(&["preview"]).inc();
+    let problems = match state.debug_mode {
+        true => content.problems_for(&slug),
+        false => Vec::new(),
+    };
+    // A draft's assets aren't served under /post/ until it's out, so point
+    // them somewhere that checks the token too.
+    let mut post = post.clone();
+    post.rendered = post.rendered.replace(
+        &format!("\"/post/{}/", slug),
+        &format!("\"/preview/{}/{}/", slug, query.token),
+    );
+    // Links to posts that are already out still work, they just aren't drafts.
+    let draft = content.post(&slug).is_none();
+    let mut response = render_blogpost(&post, &problems, draft).into_response();
+    private(response.headers_mut());
+    response
+}
+
+/// Serve a file from alongside a post to whoever has a preview link for it.
+pub async fn preview_asset(
+    Path((slug, token, file)): Path<(String, String, String)>,
+    Extension(state): Extension<Arc<State>>,
+    request: Request<Body>,
+) -> Response {
+    let content = state.content();
+    let post = content
+        .preview(&slug)
+        .filter(|_| state.preview_key.verify(&slug, &token, Utc::now()));
+    let Some(post) = post else {
+        return handle_404().await.into_response();
+    };
+    let mut response = serve_asset(post, file, request).await;
+    private(response.headers_mut());
+    response
+}
+
+/// Headers for anything only reachable through a preview link.
+fn private(headers: &mut HeaderMap) {
+    headers.insert("x-robots-tag", HeaderValue::from_static("noindex"));
+    headers.insert(CACHE_CONTROL, HeaderValue::from_static("private, no-store"));
+    // The token is in the URL, don't hand it to every link on the page.
+    headers.insert(REFERRER_POLICY, HeaderValue::from_static("no-referrer"));
+}
+
+/// Serve a file from alongside a bundled post, or one generated along with
+/// the post, such as an image from a notebook.
+pub async fn post_asset(
+    Path((slug, file)): Path<(String, String)>,
+    Extension(state): Extension<Arc<State>>,
+    request: Request<Body>,
+) -> Response {
+    let content = state.content();
+    match content.post(&slug) {
+        Some(post) => serve_asset(post, file, request).await,
+        None => handle_404().await.into_response(),
+    }
+}
+
+async fn serve_asset(post: &Post, file: String, request: Request<Body>) -> Response {
+    if let Some(asset) = post.assets.get(&file) {
+        // Notebooks only ever produce PNGs.
+        return ([(CONTENT_TYPE, "image/png")], asset.clone()).into_response();
+    }
+    let bundle = post.bundle.clone();
+    // Only plain paths inside the bundle, no wandering off with `..`, and
+    // never the post's own source, which may hold drafts or comments.
+    let file = PathBuf::from(file);
+    let inside_bundle = file.components().all(|c| matches!(c, Component::Normal(_)));
+    match bundle {
+        Some(dir) if inside_bundle && !is_post_source(&file) => {
+            match ServeFile::new(dir.join(file)).oneshot(request).await {
+                Ok(response) => response.map(body::boxed),
+                Err(e) => match e {},
+            }
+        }
+        _ => handle_404().await.into_response(),
+    }
+}
+
+/// Whether a file is one the loader would read as a post.
+fn is_post_source(file: &std::path::Path) -> bool {
+    file.extension().and_then(|e| e.to_str()).is_some_and(|e| {
+        ["md", "org", "ipynb"]
+            .iter()
+            .any(|s| e.eq_ignore_ascii_case(s))
+    })
 }
 
 pub async fn tag(
     Path(tagname): Path<String>,
     Extension(state): Extension<Arc<State>>,
-) -> (StatusCode, Markup) {
-    match render_tagged_post_list(state, tagname).await {
-        Ok(x) => (StatusCode::OK, x),
-        Err(x) => (StatusCode::BAD_REQUEST, x),
+    headers: HeaderMap,
+) -> Response {
+    let content = state.content();
+    match content.tag_page(&tagname) {
+        Some(page) => page.respond(StatusCode::OK, &headers),
+        None => (StatusCode::BAD_REQUEST, render_unknown_tag(&tagname)).into_response(),
     }
 }
 
@@ -75,33 +202,304 @@ pub async fn about() -> Markup {
     render_about().await
 }
 
-pub async fn generate_atom_feed(Extension(state): Extension<Arc<State>>) -> impl IntoResponse {
+pub async fn generate_atom_feed(
+    Extension(state): Extension<Arc<State>>,
+    headers: HeaderMap,
+) -> Response {
     FEED_HIT_COUNTER.with_label_values(&["atom"]).inc();
-    let mut headers = HeaderMap::new();
-    // Atom has it's own MIME type, we should use it.
-    headers.insert("content-type", "application/atom+xml".parse().unwrap());
-    (headers, (*state.atom_feed.read().await).to_string())
+    state.content().feeds.atom.respond(StatusCode::OK, &headers)
+}
+
+pub async fn generate_rss_feed(
+    Extension(state): Extension<Arc<State>>,
+    headers: HeaderMap,
+) -> Response {
+    FEED_HIT_COUNTER.with_label_values(&["rss"]).inc();
+    state.content().feeds.rss.respond(StatusCode::OK, &headers)
+}
+
+pub async fn generate_json_feed(
+    Extension(state): Extension<Arc<State>>,
+    headers: HeaderMap,
+) -> Response {
+    FEED_HIT_COUNTER.with_label_values(&["json"]).inc();
+    state.content().feeds.json.respond(StatusCode::OK, &headers)
+}
+
+pub async fn atom_archive(
+    Path(page): Path<String>,
+    Extension(state): Extension<Arc<State>>,
+    headers: HeaderMap,
+) -> Response {
+    FEED_HIT_COUNTER.with_label_values(&["atom_archive"]).inc();
+    let content = state.content();
+    match page
+        .parse()
+        .ok()
+        .and_then(|page| content.feeds.archive(page))
+    {
+        Some(archive) => archive.respond(StatusCode::OK, &headers),
+        None => handle_404().await.into_response(),
+    }
+}
+
+pub async fn tag_atom_feed(
+    Path(tagname): Path<String>,
+    Extension(state): Extension<Arc<State>>,
+    headers: HeaderMap,
+) -> Response {
+    FEED_HIT_COUNTER.with_label_values(&["tag_atom"]).inc();
+    match state.content().tag_feeds(&tagname) {
+        Some(feeds) => feeds.atom.respond(StatusCode::OK, &headers),
+        None => handle_404().await.into_response(),
+    }
+}
+
+pub async fn tag_rss_feed(
+    Path(tagname): Path<String>,
+    Extension(state): Extension<Arc<State>>,
+    headers: HeaderMap,
+) -> Response {
+    FEED_HIT_COUNTER.with_label_values(&["tag_rss"]).inc();
+    match state.content().tag_feeds(&tagname) {
+        Some(feeds) => feeds.rss.respond(StatusCode::OK, &headers),
+        None => handle_404().await.into_response(),
+    }
+}
+
+pub async fn tag_json_feed(
+    Path(tagname): Path<String>,
+    Extension(state): Extension<Arc<State>>,
+    headers: HeaderMap,
+) -> Response {
+    FEED_HIT_COUNTER.with_label_values(&["tag_json"]).inc();
+    match state.content().tag_feeds(&tagname) {
+        Some(feeds) => feeds.json.respond(StatusCode::OK, &headers),
+        None => handle_404().await.into_response(),
+    }
+}
+
+pub async fn tag_atom_archive(
+    Path((tagname, page)): Path<(String, String)>,
+    Extension(state): Extension<Arc<State>>,
+    headers: HeaderMap,
+) -> Response {
+    FEED_HIT_COUNTER
+        .with_label_values(&["tag_atom_archive"])
+        .inc();
+    let content = state.content();
+    let archive = page
+        .parse()
+        .ok()
+        .and_then(|page| content.tag_feeds(&tagname)?.archive(page));
+    match archive {
+        Some(archive) => archive.respond(StatusCode::OK, &headers),
+        None => handle_404().await.into_response(),
+    }
 }
 
 pub async fn reload_posts(
     Extension(state): Extension<Arc<State>>,
     Json(payload): Json<AdminToken>,
-) -> (StatusCode, Markup) {
+) -> Response {
     if payload.admin_token == state.admin_token {
-        let newposts = state.generate_posts();
-        *state.posts.write().await = newposts;
-        let new_feed = state.generate_atom_feed().await;
-        *state.atom_feed.write().await = new_feed;
-        (StatusCode::OK, html! {"Refreshed!"})
+        let diagnostics = state.reload().await;
+        (StatusCode::OK, Json(diagnostics)).into_response()
     } else {
-        (
-            StatusCode::FORBIDDEN,
-            html! {"Nothing To See Here! (For you anyway)"},
-        )
+        forbidden()
+    }
+}
+
+#[derive(Deserialize)]
+pub struct PreviewRequest {
+    admin_token: String,
+    slug: String,
+    /// How long the link should work for, in hours. Capped at
+    /// `preview::max_lifetime`.
+    hours: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct PreviewLink {
+    url: String,
+    expires: DateTime<Utc>,
+}
+
+pub async fn create_preview(
+    Extension(state): Extension<Arc<State>>,
+    Json(payload): Json<PreviewRequest>,
+) -> Response {
+    if payload.admin_token != state.admin_token {
+        return forbidden();
+    }
+    if state.content().preview(&payload.slug).is_none() {
+        return (StatusCode::NOT_FOUND, "No post with that slug").into_response();
+    }
+    let lifetime = payload
+        .hours
+        .filter(|&hours| hours > 0)
+        .map_or_else(preview::default_lifetime, |hours| {
+            Duration::hours(hours.min(preview::max_lifetime().num_hours()))
+        });
+    let expires = Utc::now() + lifetime;
+    let token = state.preview_key.mint(&payload.slug, expires);
+    let url = format!("{}/preview/{}?token={}", SITE_URL, payload.slug, token);
+    (StatusCode::OK, Json(PreviewLink { url, expires })).into_response()
+}
+
+pub async fn diagnostics(
+    Extension(state): Extension<Arc<State>>,
+    Json(payload): Json<AdminToken>,
+) -> Response {
+    if payload.admin_token == state.admin_token {
+        let diagnostics = state.content().diagnostics.clone();
+        (StatusCode::OK, Json(diagnostics)).into_response()
+    } else {
+        forbidden()
     }
 }
 
+fn forbidden() -> Response {
+    (
+        StatusCode::FORBIDDEN,
+        html! {"Nothing To See Here! (For you anyway)"},
+    )
+        .into_response()
+}
+
+pub async fn sitemap(Extension(state): Extension<Arc<State>>, headers: HeaderMap) -> Response {
+    state.content().sitemap.respond(StatusCode::OK, &headers)
+}
+
+pub async fn robots() -> String {
+    sitemap::robots()
+}
+
 pub async fn feeds() -> Markup {
     PAGE_HIT_COUNTER.with_label_values(&["feeds"]).inc();
     render_feeds().await
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, fixture_state, post, posts_dir, FIXTURES};
+
+    async fn asset(state: &Arc<State>, slug: &str, file: &str) -> StatusCode {
+        let path = Path((slug.to_string(), file.to_string()));
+        post_asset(
+            path,
+            Extension(Arc::clone(state)),
+            Request::new(Body::empty()),
+        )
+        .await
+        .status()
+    }
+
+    #[tokio::test]
+    async fn post_sources_are_not_assets() {
+        let state = fixture_state();
+
+        assert_eq!(asset(&state, "bundle", "pixel.png").await, StatusCode::OK);
+        assert_eq!(
+            asset(&state, "bundle", "index.md").await,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            asset(&state, "bundle", "../tables.md").await,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            asset(&state, "tables", "pixel.png").await,
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn preview_links_have_a_limit() {
+        let request = PreviewRequest {
+            admin_token: "test".to_string(),
+            slug: "bundle".to_string(),
+            hours: Some(i64::MAX),
+        };
+        let response = create_preview(Extension(fixture_state()), Json(request)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let link: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let expires: DateTime<Utc> = link["expires"].as_str().unwrap().parse().unwrap();
+        assert!(expires <= Utc::now() + preview::max_lifetime());
+    }
+
+    #[tokio::test]
+    async fn previews_unlock_a_drafts_assets() {
+        let draft = post("draft", "2023-04-01", "private") + "![Pixel](pixel.png)\n";
+        let pixel = std::fs::read(format!("{}/bundle/pixel.png", FIXTURES)).unwrap();
+        let dir = posts_dir(&[
+            ("draft/index.md", draft.into_bytes()),
+            ("draft/pixel.png", pixel),
+        ]);
+        let state = testing::state(dir.path(), false);
+        let token = state
+            .preview_key
+            .mint("draft", Utc::now() + Duration::hours(1));
+        let preview_asset = |token: &str, file: &str| {
+            let path = Path(("draft".to_string(), token.to_string(), file.to_string()));
+            let state = Extension(Arc::clone(&state));
+            async move {
+                preview_asset(path, state, Request::new(Body::empty()))
+                    .await
+                    .status()
+            }
+        };
+
+        assert_eq!(
+            asset(&state, "draft", "pixel.png").await,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(preview_asset(&token, "pixel.png").await, StatusCode::OK);
+        assert_eq!(
+            preview_asset(&token, "index.md").await,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            preview_asset("1.bad", "pixel.png").await,
+            StatusCode::NOT_FOUND
+        );
+
+        let page = preview(
+            Path("draft".to_string()),
+            Query(PreviewToken {
+                token: token.clone(),
+            }),
+            Extension(Arc::clone(&state)),
+        )
+        .await;
+        let body = hyper::body::to_bytes(page.into_body()).await.unwrap();
+        let src = format!("src=\"/preview/draft/{}/pixel.png\"", token);
+        assert!(std::str::from_utf8(&body).unwrap().contains(&src));
+    }
+
+    #[tokio::test]
+    async fn only_drafts_are_previewed_as_drafts() {
+        let dir = posts_dir(&[
+            ("draft.md", post("draft", "2023-04-01", "private")),
+            ("live.md", post("live", "2023-04-01", "public")),
+        ]);
+        let state = testing::state(dir.path(), false);
+        let previewed = |slug: &str| {
+            let token = state
+                .preview_key
+                .mint(slug, Utc::now() + Duration::hours(1));
+            let slug = Path(slug.to_string());
+            let state = Extension(Arc::clone(&state));
+            async move {
+                let page = preview(slug, Query(PreviewToken { token }), state).await;
+                let body = hyper::body::to_bytes(page.into_body()).await.unwrap();
+                String::from_utf8(body.to_vec()).unwrap()
+            }
+        };
+
+        assert!(previewed("draft").await.contains("draft-banner"));
+        assert!(!previewed("live").await.contains("draft-banner"));
+    }
+}
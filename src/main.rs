@@ -1,7 +1,20 @@
+mod content;
 mod domainsocket;
+mod feeds;
+mod frontmatter;
 mod handlers;
+mod notebook;
+mod org;
+mod pages;
+mod preview;
+mod render;
+mod scheduler;
+mod sitemap;
+#[cfg(test)]
+mod testing;
 mod tmpl;
 mod types;
+mod watcher;
 
 use axum::body;
 use axum::routing::get_service;
@@ -12,14 +25,10 @@ use std::os::unix::fs::PermissionsExt;
 use std::{net::SocketAddr, sync::Arc};
 
 use axum::{
-    extract::Host,
-    handler::HandlerWithoutStateExt,
-    http::header::{self, HeaderValue, CONTENT_TYPE},
-    http::{StatusCode, Uri},
-    response::Redirect,
+    http::header::{self, CONTENT_TYPE},
     response::Response,
     routing::{get, post},
-    BoxError, Extension, Router, ServiceExt,
+    Extension, Router,
 };
 use std::io::Write;
 use tokio::net::UnixListener;
@@ -61,7 +70,7 @@ async fn main() {
     tracing_subscriber::fmt::init();
 
     info!("Getting site settings");
-    let ss = SiteSettings::default();
+    let ss = SiteSettings::from_env();
 
     info!("Generating new admin token");
     let admin_token = create_admin_token();
@@ -72,6 +81,12 @@ async fn main() {
     info!("Init state");
     let state: Arc<State> = Arc::new(State::new(ss, admin_token, dbg_mode));
 
+    info!("Watching posts for changes");
+    state.watch_posts();
+
+    info!("Scheduling posts that aren't out yet");
+    state.publish_on_schedule();
+
     info!("Setting up static file service");
     let staticfiles = ServeDir::new("static");
 
@@ -96,15 +111,25 @@ async fn main() {
         .route("/blog", get(handlers::list_posts))
         .route("/about", get(handlers::about))
         .route("/post/:slug", get(handlers::blogpost))
+        .route("/post/:slug/*file", get(handlers::post_asset))
+        .route("/preview/:slug", get(handlers::preview))
+        .route("/preview/:slug/:token/*file", get(handlers::preview_asset))
         .route("/tag/:tag", get(handlers::tag))
         .route("/feeds", get(handlers::feeds))
         .route("/feeds/atom.xml", get(handlers::generate_atom_feed))
+        .route("/feeds/rss.xml", get(handlers::generate_rss_feed))
+        .route("/feeds/feed.json", get(handlers::generate_json_feed))
+        .route("/feeds/archive/:page", get(handlers::atom_archive))
+        .route("/tag/:tag/atom.xml", get(handlers::tag_atom_feed))
+        .route("/tag/:tag/rss.xml", get(handlers::tag_rss_feed))
+        .route("/tag/:tag/feed.json", get(handlers::tag_json_feed))
+        .route("/tag/:tag/archive/:page", get(handlers::tag_atom_archive))
         .route("/api/admin/reload", post(handlers::reload_posts))
+        .route("/api/admin/diagnostics", post(handlers::diagnostics))
+        .route("/api/admin/preview", post(handlers::create_preview))
         .route("/metrics", get(metrics))
-        .route(
-            "/robots.txt",
-            get_service(ServeFile::new("./static/robots.txt")),
-        )
+        .route("/sitemap.xml", get(handlers::sitemap))
+        .route("/robots.txt", get(handlers::robots))
         .route(
             "/favicon.ico",
             get_service(ServeFile::new("./static/assets/favicon.ico")),
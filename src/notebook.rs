@@ -0,0 +1,299 @@
+use axum::body::Bytes;
+use base64::Engine;
+use comrak::Anchorizer;
+use maud::html;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use tracing::*;
+
+use crate::frontmatter::FrontMatter;
+use crate::render::{fenced, Heading, Renderer};
+use crate::types::PostParseError;
+
+#[derive(Deserialize)]
+struct Notebook {
+    cells: Vec<Cell>,
+    #[serde(default)]
+    metadata: Metadata,
+}
+
+#[derive(Deserialize, Default)]
+struct Metadata {
+    language_info: Option<LanguageInfo>,
+}
+
+#[derive(Deserialize)]
+struct LanguageInfo {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct Cell {
+    cell_type: String,
+    source: MultilineString,
+    #[serde(default)]
+    outputs: Vec<Output>,
+}
+
+#[derive(Deserialize)]
+struct Output {
+    output_type: String,
+    text: Option<MultilineString>,
+    #[serde(default)]
+    data: HashMap<String, Value>,
+    ename: Option<String>,
+    evalue: Option<String>,
+}
+
+/// Notebooks store text either as one string or as a list of lines.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum MultilineString {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl MultilineString {
+    fn joined(&self) -> String {
+        match self {
+            MultilineString::One(s) => s.clone(),
+            MultilineString::Many(lines) => lines.concat(),
+        }
+    }
+}
+
+/// A notebook turned into something we can make a `Post` from.
+pub struct RenderedNotebook {
+    pub frontmatter: FrontMatter,
+    pub rendered: String,
+    /// The markdown and code from the notebook, for working out read time.
+    pub source: String,
+    /// Headings from the markdown cells, if the notebook asks for a table of
+    /// contents.
+    pub toc: Vec<Heading>,
+    /// Images from the outputs, by file name, to be served alongside the post.
+    pub assets: HashMap<String, Bytes>,
+}
+
+/// Render a notebook, taking its frontmatter from the first markdown or raw
+/// cell. Markdown and code cells go through the usual renderer, and whatever
+/// output was saved in the notebook comes along underneath each code cell.
+pub fn render(content: &str, renderer: &Renderer) -> Result<RenderedNotebook, PostParseError> {
+    let notebook: Notebook =
+        serde_json::from_str(content).map_err(|e| PostParseError::InvalidNotebook {
+            message: e.to_string(),
+            line: Some(e.line()),
+            column: Some(e.column()),
+        })?;
+    let language = notebook
+        .metadata
+        .language_info
+        .map(|l| l.name)
+        .unwrap_or_default();
+
+    let first_text_cell = notebook
+        .cells
+        .iter()
+        .position(|c| c.cell_type == "markdown" || c.cell_type == "raw")
+        .ok_or(PostParseError::NoFrontmatter)?;
+    let first_source = notebook.cells[first_text_cell].source.joined();
+    let (frontmatter, rest) = FrontMatter::new(&first_source)?;
+
+    let mut rendered = String::new();
+    let mut source = String::new();
+    let mut toc = Vec::new();
+    let mut anchorizer = Anchorizer::new();
+    let mut assets = HashMap::new();
+    let base = format!("/post/{}/", frontmatter.slug);
+    for (i, cell) in notebook.cells.iter().enumerate() {
+        let text = match i == first_text_cell {
+            true => rest.to_string(),
+            false => cell.source.joined(),
+        };
+        match cell.cell_type.as_str() {
+            "markdown" | "raw" => {
+                let (html, headings) =
+                    renderer.render_section(&text, frontmatter.toc, &mut anchorizer);
+                rendered.push_str(&html);
+                toc.extend(headings);
+            }
+            "code" => {
+                rendered.push_str(&renderer.render(&fenced(&text, &language)));
+                for output in cell.outputs.iter() {
+                    rendered.push_str(&render_output(output, &base, &mut assets));
+                }
+            }
+            _ => continue,
+        }
+        source.push_str(&text);
+        source.push('\n');
+    }
+
+    Ok(RenderedNotebook {
+        frontmatter,
+        rendered,
+        source,
+        toc,
+        assets,
+    })
+}
+
+fn render_output(output: &Output, base: &str, assets: &mut HashMap<String, Bytes>) -> String {
+    let text = |s: &Value| match s {
+        Value::String(s) => s.clone(),
+        Value::Array(lines) => lines.iter().filter_map(Value::as_str).collect(),
+        _ => String::new(),
+    };
+    let content = match output.output_type.as_str() {
+        "stream" => output
+            .text
+            .as_ref()
+            .map(|t| html! { pre { (t.joined()) } }.into_string()),
+        "execute_result" | "display_data" => {
+            if let Some(png) = output.data.get("image/png") {
+                Some(image(&text(png), base, assets))
+            } else if let Some(markup) = output.data.get("text/html") {
+                Some(text(markup))
+            } else {
+                output
+                    .data
+                    .get("text/plain")
+                    .map(|plain| html! { pre { (text(plain)) } }.into_string())
+            }
+        }
+        "error" => Some(
+            html! {
+                pre class="notebook-error" {
+                    (output.ename.as_deref().unwrap_or_default()) ": "
+                    (output.evalue.as_deref().unwrap_or_default())
+                }
+            }
+            .into_string(),
+        ),
+        _ => None,
+    };
+    match content {
+        Some(content) => format!("<div class=\"notebook-output\">{}</div>", content),
+        None => String::new(),
+    }
+}
+
+/// Pull a PNG output out into an asset of the post and link to it there,
+/// rather than inlining it into every copy of the page. Images are named
+/// after their contents, so the same image is only kept once.
+fn image(encoded: &str, base: &str, assets: &mut HashMap<String, Bytes>) -> String {
+    let encoded: String = encoded.split_whitespace().collect();
+    let bytes = match base64::engine::general_purpose::STANDARD.decode(&encoded) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            warn!("Notebook has an image that isn't valid base64: {}", e);
+            return String::new();
+        }
+    };
+    let mut hasher = DefaultHasher::new();
+    bytes.hash(&mut hasher);
+    let filename = format!("{:016x}.png", hasher.finish());
+    let src = format!("{}{}", base, filename);
+    assets.insert(filename, Bytes::from(bytes));
+    html! { img src=(src) alt="Notebook output"; }.into_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::SiteSettings;
+
+    const PNG: &str = "iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAYAAAAfFcSJAAAADUlEQVR42mNkYPhfDwAChwGA60e6kgAAAABJRU5ErkJggg==";
+
+    fn renderer() -> Renderer {
+        Renderer::new(&SiteSettings::default())
+    }
+
+    fn notebook() -> String {
+        serde_json::json!({
+            "metadata": {"language_info": {"name": "python"}},
+            "cells": [
+                {"cell_type": "raw", "source": ["---\n", "title: A Notebook\n", "slug: nb\n", "published: 2023-04-05\n", "tags: [ml]\n", "public: true\n", "---\n"]},
+                {"cell_type": "markdown", "source": "# Results\n\nThey're *in*.\n\n## Details"},
+                {"cell_type": "code", "source": ["import numpy as np\n", "print(np.pi)"], "outputs": [
+                    {"output_type": "stream", "name": "stdout", "text": ["3.14 < 4\n"]},
+                    {"output_type": "execute_result", "data": {"text/html": "<b>table</b>", "text/plain": "table"}},
+                    {"output_type": "display_data", "data": {"image/png": PNG, "text/plain": "<Figure>"}}
+                ]}
+            ]
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn renders_cells_and_outputs() {
+        let nb = render(&notebook(), &renderer()).unwrap();
+        assert_eq!(nb.frontmatter.slug, "nb");
+        assert!(nb.rendered.contains("<h1>Results</h1>"));
+        assert!(nb.rendered.contains("<pre style="));
+        assert!(nb.rendered.contains("<pre>3.14 &lt; 4\n</pre>"));
+        assert!(nb.rendered.contains("<b>table</b>"));
+        assert!(!nb.rendered.contains("title: A Notebook"));
+        assert!(nb.toc.is_empty());
+    }
+
+    #[test]
+    fn images_are_served_with_the_post() {
+        let nb = render(&notebook(), &renderer()).unwrap();
+        assert_eq!(nb.assets.len(), 1);
+        let (name, png) = nb.assets.iter().next().unwrap();
+        assert!(nb.rendered.contains(&format!("src=\"/post/nb/{}\"", name)));
+        assert_eq!(
+            png.as_ref(),
+            base64::engine::general_purpose::STANDARD
+                .decode(PNG)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn table_of_contents() {
+        let notebook = notebook().replace("public: true\\n", "public: true\\n\", \"toc: true\\n");
+        let nb = render(&notebook, &renderer()).unwrap();
+        assert!(nb.frontmatter.toc);
+        let anchors: Vec<_> = nb.toc.iter().map(|h| h.anchor.as_str()).collect();
+        assert_eq!(anchors, ["results", "details"]);
+        assert!(nb.rendered.contains("id=\"results\""));
+    }
+
+    #[test]
+    fn headings_repeated_across_cells_get_their_own_anchors() {
+        let notebook = serde_json::json!({
+            "cells": [
+                {"cell_type": "raw", "source": "---\ntitle: Twice\nslug: twice\npublished: 2023-04-05\ntags: []\npublic: true\ntoc: true\n---\n# Setup\n"},
+                {"cell_type": "markdown", "source": "# Setup\n\n## Setup"}
+            ]
+        })
+        .to_string();
+        let nb = render(&notebook, &renderer()).unwrap();
+        let anchors: Vec<_> = nb.toc.iter().map(|h| h.anchor.as_str()).collect();
+        assert_eq!(anchors, ["setup", "setup-1", "setup-2"]);
+        for anchor in anchors {
+            assert_eq!(
+                nb.rendered.matches(&format!("id=\"{}\"", anchor)).count(),
+                1
+            );
+        }
+    }
+
+    #[test]
+    fn bad_notebooks_are_reported() {
+        let renderer = renderer();
+        assert!(matches!(
+            render("{\"cells\": [}", &renderer),
+            Err(PostParseError::InvalidNotebook { line: Some(1), .. })
+        ));
+        assert!(matches!(
+            render("{\"cells\": []}", &renderer),
+            Err(PostParseError::NoFrontmatter)
+        ));
+    }
+}
@@ -0,0 +1,503 @@
+use serde_json::{Map, Value};
+
+use crate::frontmatter::{FrontMatter, FrontMatterFormat};
+use crate::render::fenced;
+use crate::types::PostParseError;
+
+/// Split an org file into its frontmatter, taken from the `#+KEYWORD:` lines,
+/// and the rest of the post translated to markdown.
+/// Going via markdown means org posts get rendered by exactly the same
+/// pipeline as everything else, highlighting and footnotes included.
+pub fn parse(content: &str) -> Result<(FrontMatter, String), PostParseError> {
+    let content = content.strip_prefix('\u{feff}').unwrap_or(content);
+    let mut keywords = Map::new();
+    let mut markdown = String::new();
+    let mut lines = content.lines().peekable();
+
+    while let Some(line) = lines.next() {
+        let trimmed = line.trim_start();
+
+        if let Some(lang) = block_start(trimmed, "src").or_else(|| block_start(trimmed, "example"))
+        {
+            // Everything up to the end of the block goes through verbatim.
+            let lang = lang.split_whitespace().next().unwrap_or_default();
+            let mut code = Vec::new();
+            for line in lines.by_ref() {
+                if is_block_end(line.trim_start()) {
+                    break;
+                }
+                code.push(unescape_block_line(line));
+            }
+            markdown.push_str(&fenced(&code.join("\n"), lang));
+        } else if block_start(trimmed, "quote").is_some() {
+            for line in lines.by_ref() {
+                if is_block_end(line.trim_start()) {
+                    break;
+                }
+                markdown.push_str(&format!("> {}\n", inline(line.trim())));
+            }
+            markdown.push('\n');
+        } else if is_block_start(trimmed) || is_block_end(trimmed) {
+            // Any other kind of block, we just keep what's inside it.
+        } else if let Some((key, value)) = keyword(trimmed) {
+            keywords_into_frontmatter(&mut keywords, &key, value);
+        } else if trimmed == "#" || trimmed.starts_with("# ") {
+            // Comment, nothing to render.
+        } else if trimmed.eq_ignore_ascii_case(":properties:") {
+            for line in lines.by_ref() {
+                if line.trim().eq_ignore_ascii_case(":end:") {
+                    break;
+                }
+            }
+        } else if let Some(heading) = heading(line) {
+            markdown.push_str(&heading);
+        } else if trimmed.starts_with('|') {
+            let mut table = vec![trimmed];
+            while let Some(next) = lines.peek() {
+                if !next.trim_start().starts_with('|') {
+                    break;
+                }
+                table.push(lines.next().unwrap().trim_start());
+            }
+            markdown.push_str(&self::table(&table));
+        } else if trimmed == ":" || trimmed.starts_with(": ") {
+            // Fixed width lines, which are code blocks in all but name.
+            let mut code = vec![trimmed.get(2..).unwrap_or_default()];
+            while let Some(next) = lines.peek() {
+                let next = next.trim_start();
+                if next != ":" && !next.starts_with(": ") {
+                    break;
+                }
+                code.push(next.get(2..).unwrap_or_default());
+                lines.next();
+            }
+            markdown.push_str(&fenced(&code.join("\n"), ""));
+        } else if let Some((name, text)) = footnote_definition(line) {
+            markdown.push_str(&format!("[^{}]: {}\n", name, inline(text)));
+        } else {
+            markdown.push_str(&inline(line));
+            markdown.push('\n');
+        }
+    }
+
+    if keywords.is_empty() {
+        return Err(PostParseError::NoFrontmatter);
+    }
+    let frontmatter = serde_json::from_value(Value::Object(keywords)).map_err(|e| {
+        PostParseError::FrontmatterError {
+            format: FrontMatterFormat::Org,
+            message: e.to_string(),
+            line: None,
+            column: None,
+        }
+    })?;
+    Ok((frontmatter, markdown))
+}
+
+/// Map the keywords we know about onto the fields of `FrontMatter`.
+/// `#+OPTIONS:` only counts for its `toc:` setting, anything else in it has no
+/// equivalent and is dropped. Other keywords are passed on as they are, so
+/// they get reported as unknown like any other frontmatter key.
+fn keywords_into_frontmatter(fields: &mut Map<String, Value>, key: &str, value: &str) {
+    let value = value.trim();
+    match key {
+        "title" | "slug" | "description" | "author" | "cover_image" | "canonical_url" => {
+            fields.insert(key.to_string(), Value::String(value.to_string()));
+        }
+        "date" | "published" => {
+            fields.insert("published".to_string(), Value::String(date(value)));
+        }
+        "updated" | "last_modified" => {
+            fields.insert("updated".to_string(), Value::String(date(value)));
+        }
+        "filetags" | "tags" => {
+            let tags = value
+                .split(|c: char| c == ':' || c.is_whitespace())
+                .filter(|t| !t.is_empty())
+                .map(|t| Value::String(t.to_string()))
+                .collect();
+            fields.insert("tags".to_string(), Value::Array(tags));
+        }
+        "language" | "lang" => {
+            fields.insert("lang".to_string(), Value::String(value.to_string()));
+        }
+        "options" => {
+            let toc = value
+                .split_whitespace()
+                .find_map(|option| option.strip_prefix("toc:"));
+            if let Some(toc) = toc {
+                fields.insert("toc".to_string(), Value::Bool(toc != "nil"));
+            }
+        }
+        "public" => {
+            let public = matches!(value.to_lowercase().as_str(), "t" | "true" | "yes");
+            fields.insert("visibility".to_string(), Value::Bool(public));
+        }
+        "visibility" => {
+            fields.insert(
+                "visibility".to_string(),
+                Value::String(value.to_lowercase()),
+            );
+        }
+        // These belong to whatever follows them in the body rather than to
+        // the post. `#+TOC:` places a table of contents, ours always goes at
+        // the top.
+        "caption" | "name" | "results" | "header" | "plot" | "toc" => {}
+        _ if key.starts_with("attr_") => {}
+        _ => {
+            fields.insert(key.to_string(), Value::String(value.to_string()));
+        }
+    }
+}
+
+/// Org timestamps look like `<2023-04-01 Sat>` or `<2023-04-01 Sat 10:30>`.
+/// The day name goes, and a time comes along as a local time, so it's read
+/// in the site's timezone like any other timestamp without an offset.
+fn date(value: &str) -> String {
+    let mut parts = value
+        .trim_matches(|c| c == '<' || c == '>' || c == '[' || c == ']')
+        .split_whitespace();
+    let date = parts.next().unwrap_or_default();
+    // A time range like `10:30-11:00` starts at its first time.
+    let time = parts
+        .filter_map(|part| part.split('-').next())
+        .find_map(|part| chrono::NaiveTime::parse_from_str(part, "%H:%M").ok());
+    match time {
+        Some(time) => format!("{}T{}", date, time.format("%H:%M:%S")),
+        None => date.to_string(),
+    }
+}
+
+fn keyword(line: &str) -> Option<(String, &str)> {
+    let rest = line.strip_prefix("#+")?;
+    let (key, value) = rest.split_once(':')?;
+    if key.is_empty() || key.contains(char::is_whitespace) {
+        return None;
+    }
+    Some((key.to_lowercase(), value))
+}
+
+fn block_start<'a>(line: &'a str, kind: &str) -> Option<&'a str> {
+    let marker = format!("#+begin_{}", kind);
+    let head = line.get(..marker.len())?;
+    if !head.eq_ignore_ascii_case(&marker) {
+        return None;
+    }
+    let rest = &line[marker.len()..];
+    if rest.is_empty() || rest.starts_with(char::is_whitespace) {
+        Some(rest.trim())
+    } else {
+        None
+    }
+}
+
+fn is_block_start(line: &str) -> bool {
+    line.get(..8)
+        .is_some_and(|head| head.eq_ignore_ascii_case("#+begin_"))
+}
+
+fn is_block_end(line: &str) -> bool {
+    line.get(..6)
+        .is_some_and(|head| head.eq_ignore_ascii_case("#+end_"))
+}
+
+/// Org escapes lines in blocks that would otherwise look like headings or
+/// keywords with a leading comma.
+fn unescape_block_line(line: &str) -> &str {
+    let trimmed = line.trim_start();
+    match trimmed.strip_prefix(',') {
+        Some(rest) if rest.starts_with('*') || rest.starts_with("#+") => rest,
+        _ => line,
+    }
+}
+
+fn heading(line: &str) -> Option<String> {
+    let level = line.chars().take_while(|&c| c == '*').count();
+    if level == 0 || !line[level..].starts_with(' ') {
+        return None;
+    }
+    let mut text = line[level..].trim();
+    // Drop any trailing :tags: on the heading.
+    if let Some((title, tags)) = text.rsplit_once(char::is_whitespace) {
+        if tags.len() > 1 && tags.starts_with(':') && tags.ends_with(':') {
+            text = title.trim_end();
+        }
+    }
+    Some(format!("{} {}\n", "#".repeat(level.min(6)), inline(text)))
+}
+
+fn footnote_definition(line: &str) -> Option<(&str, &str)> {
+    let rest = line.strip_prefix("[fn:")?;
+    let (name, text) = rest.split_once(']')?;
+    if name.is_empty() || name.contains(':') {
+        return None;
+    }
+    Some((name, text.trim()))
+}
+
+/// Org tables only need a rule under the header if they have one, markdown
+/// always wants one. Any other rules are just decoration, so they go.
+fn table(rows: &[&str]) -> String {
+    let is_rule = |row: &str| row.starts_with("|-");
+    let cells = |row: &str| -> Vec<String> {
+        row.trim()
+            .trim_matches('|')
+            .split('|')
+            .map(|cell| inline(cell.trim()))
+            .collect()
+    };
+    let body: Vec<Vec<String>> = rows
+        .iter()
+        .filter(|r| !is_rule(r))
+        .map(|r| cells(r))
+        .collect();
+    let columns = body.iter().map(Vec::len).max().unwrap_or(0);
+    if columns == 0 {
+        return String::new();
+    }
+    let has_header = rows.len() > 1 && !is_rule(rows[0]) && is_rule(rows[1]);
+    let empty = vec![String::new(); columns];
+    let (header, body) = match has_header {
+        true => (&body[0], &body[1..]),
+        false => (&empty, &body[..]),
+    };
+    let row = |cells: &Vec<String>| {
+        let mut line = String::from("|");
+        for i in 0..columns {
+            line.push_str(&format!(
+                " {} |",
+                cells.get(i).map(String::as_str).unwrap_or("")
+            ));
+        }
+        line.push('\n');
+        line
+    };
+    let mut out = String::from("\n");
+    out.push_str(&row(header));
+    out.push_str(&format!("|{}\n", " --- |".repeat(columns)));
+    for cells in body {
+        out.push_str(&row(cells));
+    }
+    out.push('\n');
+    out
+}
+
+/// Translate org's inline markup, links and footnote references into their
+/// markdown equivalents.
+fn inline(text: &str) -> String {
+    let mut out = String::new();
+    let mut i = 0;
+    while i < text.len() {
+        let rest = &text[i..];
+        if let Some(inner) = rest.strip_prefix("[[") {
+            if let Some(end) = inner.find("]]") {
+                out.push_str(&link(&inner[..end]));
+                i += end + 4;
+                continue;
+            }
+        }
+        if let Some(inner) = rest.strip_prefix("[fn:") {
+            if let Some(end) = inner.find(']') {
+                let name = &inner[..end];
+                if !name.is_empty() && !name.contains(':') {
+                    out.push_str(&format!("[^{}]", name));
+                    i += end + 5;
+                    continue;
+                }
+            }
+        }
+        if let Some((len, converted)) = emphasis(text, i) {
+            out.push_str(&converted);
+            i += len;
+            continue;
+        }
+        let c = rest.chars().next().unwrap();
+        out.push(c);
+        i += c.len_utf8();
+    }
+    out
+}
+
+fn link(inner: &str) -> String {
+    let (target, description) = match inner.split_once("][") {
+        Some((target, description)) => (target, Some(description)),
+        None => (inner, None),
+    };
+    let target = target.strip_prefix("file:").unwrap_or(target);
+    match description {
+        Some(description) => format!("[{}]({})", inline(description), target),
+        None if is_image(target) => format!("![]({})", target),
+        None => format!("[{}]({})", target, target),
+    }
+}
+
+fn is_image(target: &str) -> bool {
+    let lower = target.to_lowercase();
+    [".png", ".jpg", ".jpeg", ".gif", ".svg", ".webp"]
+        .iter()
+        .any(|ext| lower.ends_with(ext))
+}
+
+/// Try to read an emphasised span starting at byte `start`, returning how
+/// many bytes it covers and its markdown.
+fn emphasis(text: &str, start: usize) -> Option<(usize, String)> {
+    let marker = text[start..].chars().next()?;
+    let (open, close, verbatim) = match marker {
+        '*' => ("**", "**", false),
+        '/' | '_' => ("*", "*", false),
+        '+' => ("~~", "~~", false),
+        '=' | '~' => ("`", "`", true),
+        _ => return None,
+    };
+    let before_ok = text[..start]
+        .chars()
+        .next_back()
+        .is_none_or(|c| c.is_whitespace() || "-('\"{".contains(c));
+    // All the markers are ascii, so they're a byte long.
+    let body = &text[start + 1..];
+    let first = body.chars().next()?;
+    if !before_ok || first.is_whitespace() || first == marker {
+        return None;
+    }
+    let (end, _) = body.char_indices().skip(1).find(|&(j, c)| {
+        c == marker
+            && !body[..j].ends_with(char::is_whitespace)
+            && body[j + 1..]
+                .chars()
+                .next()
+                .is_none_or(|c| c.is_whitespace() || "-.,:!?;'\")}[".contains(c))
+    })?;
+    let inner = &body[..end];
+    let inner = if verbatim {
+        inner.to_string()
+    } else {
+        inline(inner)
+    };
+    Some((end + 2, format!("{}{}{}", open, inner, close)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Visibility;
+
+    const KEYWORDS: &str = "#+TITLE: An Org Post\n#+SLUG: org-post\n#+DATE: <2023-04-01 Sat>\n#+FILETAGS: :emacs:org:\n#+PUBLIC: t\n";
+
+    #[test]
+    fn keywords_become_frontmatter() {
+        let (fm, _) = parse(KEYWORDS).unwrap();
+        assert_eq!(fm.title, "An Org Post");
+        assert_eq!(fm.slug, "org-post");
+        assert_eq!(fm.published.to_string(), "2023-04-01");
+        assert_eq!(fm.tags, vec!["emacs", "org"]);
+        assert_eq!(fm.visibility, Visibility::Public);
+    }
+
+    #[test]
+    fn optional_keywords() {
+        let post = format!(
+            "{}#+DESCRIPTION: Short\n#+AUTHOR: Guest\n#+LANGUAGE: de\n#+OPTIONS: num:nil toc:2\n",
+            KEYWORDS
+        );
+        let (fm, _) = parse(&post).unwrap();
+        assert_eq!(fm.description.as_deref(), Some("Short"));
+        assert_eq!(fm.author.as_deref(), Some("Guest"));
+        assert_eq!(fm.lang.as_deref(), Some("de"));
+        assert!(fm.toc);
+
+        let (fm, _) = parse(&format!("{}#+OPTIONS: toc:nil\n", KEYWORDS)).unwrap();
+        assert!(!fm.toc);
+    }
+
+    #[test]
+    fn unknown_keywords_are_kept_track_of() {
+        let post = format!(
+            "{}#+STARTUP: overview\n#+SUBTITLE: More\n#+TOC: headlines 2\n#+CAPTION: A table\n#+ATTR_HTML: :width 50%\n| a |\n",
+            KEYWORDS
+        );
+        let (fm, _) = parse(&post).unwrap();
+        assert_eq!(fm.unknown_keys(), vec!["startup", "subtitle"]);
+        assert!(!fm.toc);
+    }
+
+    #[test]
+    fn timestamps_keep_their_time() {
+        assert_eq!(date("<2023-04-01 Sat>"), "2023-04-01");
+        assert_eq!(date("<2023-04-01 Sat 10:30>"), "2023-04-01T10:30:00");
+        assert_eq!(date("[2023-04-01 Sat 9:05]"), "2023-04-01T09:05:00");
+        assert_eq!(
+            date("<2023-04-01 Sat 10:30-11:00 +1w>"),
+            "2023-04-01T10:30:00"
+        );
+
+        let post = KEYWORDS.replace("<2023-04-01 Sat>", "<2023-04-01 Sat 10:30>");
+        let (fm, _) = parse(&post).unwrap();
+        let tokyo: chrono_tz::Tz = "Asia/Tokyo".parse().unwrap();
+        assert_eq!(
+            fm.published.in_timezone(tokyo).to_rfc3339(),
+            "2023-04-01T01:30:00+00:00"
+        );
+    }
+
+    #[test]
+    fn missing_keywords_are_reported() {
+        assert!(matches!(
+            parse("#+TITLE: Nothing Else\n"),
+            Err(PostParseError::FrontmatterError {
+                format: FrontMatterFormat::Org,
+                ..
+            })
+        ));
+        assert!(matches!(
+            parse("* Just a heading\n"),
+            Err(PostParseError::NoFrontmatter)
+        ));
+    }
+
+    #[test]
+    fn translates_structure() {
+        let post = format!(
+            "{}* Heading :tag:\n** Sub\n#+BEGIN_SRC rust\nfn main() {{}}\n,* not a heading\n#+END_SRC\n# a comment\n| a | b |\n|---+---|\n| 1 | 2 |\n",
+            KEYWORDS
+        );
+        let (_, md) = parse(&post).unwrap();
+        assert_eq!(
+            md,
+            "# Heading\n## Sub\n```rust\nfn main() {}\n* not a heading\n```\n\n| a | b |\n| --- | --- |\n| 1 | 2 |\n\n"
+        );
+    }
+
+    #[test]
+    fn blocks_can_hold_fences() {
+        let post = format!(
+            "{}#+BEGIN_SRC md\n```rust\nfn main() {{}}\n```\n#+END_SRC\nAfter\n: ````\n",
+            KEYWORDS
+        );
+        let (_, markdown) = parse(&post).unwrap();
+        assert_eq!(
+            markdown,
+            "````md\n```rust\nfn main() {}\n```\n````\nAfter\n`````\n````\n`````\n"
+        );
+    }
+
+    #[test]
+    fn translates_inline_markup() {
+        assert_eq!(
+            inline("*bold* /italic/ =code= ~verb~ +gone+"),
+            "**bold** *italic* `code` `verb` ~~gone~~"
+        );
+        assert_eq!(
+            inline("see [[https://nixos.org][Nix /itself/]] or [[https://example.com]]"),
+            "see [Nix *itself*](https://nixos.org) or [https://example.com](https://example.com)"
+        );
+        assert_eq!(inline("[[file:cat.png]]"), "![](cat.png)");
+        assert_eq!(inline("a claim[fn:1]"), "a claim[^1]");
+        assert_eq!(inline("2*3*4 and a/b/c"), "2*3*4 and a/b/c");
+    }
+
+    #[test]
+    fn footnote_definitions() {
+        let (_, md) = parse(&format!("{}[fn:1] The source.\n", KEYWORDS)).unwrap();
+        assert_eq!(md, "[^1]: The source.\n");
+    }
+}
@@ -0,0 +1,297 @@
+use axum::body::{self, Bytes, Empty, Full};
+use axum::http::header::{
+    ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH,
+    LAST_MODIFIED, VARY,
+};
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
+use axum::response::Response;
+use chrono::{DateTime, Utc};
+use flate2::write::GzEncoder;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::io::Write;
+
+pub const HTML: &str = "text/html; charset=utf-8";
+
+/// The format `Last-Modified` and `If-Modified-Since` use.
+const HTTP_DATE: &str = "%a, %d %b %Y %H:%M:%S GMT";
+
+/// The best settings of either encoder are very slow for the size win they
+/// give, these keep a full rebuild of the site quick enough to do on every save.
+const GZIP_LEVEL: flate2::Compression = flate2::Compression::new(6);
+const BROTLI_QUALITY: u32 = 9;
+const BROTLI_WINDOW: u32 = 22;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Identity,
+    Gzip,
+    Brotli,
+}
+
+impl Encoding {
+    /// Pick the best encoding the client says it'll take.
+    pub fn negotiate(headers: &HeaderMap) -> Encoding {
+        let accepted: Vec<(String, bool)> = headers
+            .get_all(ACCEPT_ENCODING)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(|coding| {
+                let mut parts = coding.split(';');
+                let name = parts.next().unwrap_or_default().trim().to_lowercase();
+                // A q of zero means "anything but this".
+                let refused = parts.any(|param| {
+                    param
+                        .trim()
+                        .strip_prefix("q=")
+                        .and_then(|q| q.trim().parse::<f32>().ok())
+                        == Some(0.0)
+                });
+                (name, !refused)
+            })
+            .collect();
+        let accepts = |name: &str| {
+            accepted
+                .iter()
+                .find(|(coding, _)| coding == name)
+                .or_else(|| accepted.iter().find(|(coding, _)| coding == "*"))
+                .is_some_and(|(_, ok)| *ok)
+        };
+        if accepts("br") {
+            Encoding::Brotli
+        } else if accepts("gzip") {
+            Encoding::Gzip
+        } else {
+            Encoding::Identity
+        }
+    }
+
+    fn header(self) -> Option<&'static str> {
+        match self {
+            Encoding::Identity => None,
+            Encoding::Gzip => Some("gzip"),
+            Encoding::Brotli => Some("br"),
+        }
+    }
+}
+
+/// A page rendered once per content snapshot, along with compressed copies so
+/// no request has to do any work beyond picking one.
+pub struct Page {
+    content_type: &'static str,
+    identity: Bytes,
+    gzip: Bytes,
+    brotli: Bytes,
+    /// Hash of the uncompressed page. Each encoding gets its own strong ETag
+    /// built from this, as they're different bytes on the wire.
+    hash: String,
+    last_modified: Option<DateTime<Utc>>,
+}
+
+impl Page {
+    pub fn html(html: String, last_modified: Option<DateTime<Utc>>) -> Self {
+        Page::new(html, HTML, last_modified)
+    }
+
+    pub fn new(
+        content: String,
+        content_type: &'static str,
+        last_modified: Option<DateTime<Utc>>,
+    ) -> Self {
+        let mut hasher = DefaultHasher::new();
+        content.hash(&mut hasher);
+        let hash = format!("{:016x}", hasher.finish());
+        let identity = Bytes::from(content);
+
+        let mut gzip = GzEncoder::new(Vec::new(), GZIP_LEVEL);
+        gzip.write_all(&identity).unwrap();
+        let gzip = Bytes::from(gzip.finish().unwrap());
+
+        let mut brotli = Vec::new();
+        {
+            let mut writer =
+                brotli::CompressorWriter::new(&mut brotli, 4096, BROTLI_QUALITY, BROTLI_WINDOW);
+            writer.write_all(&identity).unwrap();
+        }
+        let brotli = Bytes::from(brotli);
+
+        Self {
+            content_type,
+            identity,
+            gzip,
+            brotli,
+            hash,
+            last_modified,
+        }
+    }
+
+    pub fn body(&self, encoding: Encoding) -> Bytes {
+        match encoding {
+            Encoding::Identity => self.identity.clone(),
+            Encoding::Gzip => self.gzip.clone(),
+            Encoding::Brotli => self.brotli.clone(),
+        }
+    }
+
+    fn etag(&self, encoding: Encoding) -> String {
+        match encoding.header() {
+            Some(coding) => format!("\"{}-{}\"", self.hash, coding),
+            None => format!("\"{}\"", self.hash),
+        }
+    }
+
+    /// Whether the client already has this page, going by the validators
+    /// they sent. `If-None-Match` wins when both are there.
+    fn not_modified(&self, request: &HeaderMap) -> bool {
+        let tags: Vec<&str> = request
+            .get_all(IF_NONE_MATCH)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .collect();
+        if !tags.is_empty() {
+            // Weak comparison, and any encoding of the same page counts.
+            return tags.iter().any(|tag| {
+                let tag = tag.trim_start_matches("W/").trim_matches('"');
+                tag == "*"
+                    || tag
+                        .split_once('-')
+                        .map_or(tag, |(hash, _)| hash)
+                        .eq(&self.hash)
+            });
+        }
+        let since = request
+            .get(IF_MODIFIED_SINCE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| DateTime::parse_from_rfc2822(value).ok());
+        match (since, self.last_modified) {
+            // HTTP dates only go down to the second.
+            (Some(since), Some(modified)) => modified.timestamp() <= since.timestamp(),
+            _ => false,
+        }
+    }
+
+    /// Respond with whichever copy of the page suits the request, or with a
+    /// bare 304 if they've already got it.
+    pub fn respond(&self, status: StatusCode, request: &HeaderMap) -> Response {
+        let encoding = Encoding::negotiate(request);
+        let mut response = Response::builder()
+            .header(VARY, HeaderValue::from_static("accept-encoding"))
+            .header(ETAG, self.etag(encoding));
+        if let Some(modified) = self.last_modified {
+            response = response.header(LAST_MODIFIED, modified.format(HTTP_DATE).to_string());
+        }
+        if status == StatusCode::OK && self.not_modified(request) {
+            return response
+                .status(StatusCode::NOT_MODIFIED)
+                .body(body::boxed(Empty::new()))
+                .unwrap();
+        }
+        response = response
+            .status(status)
+            .header(CONTENT_TYPE, self.content_type);
+        if let Some(coding) = encoding.header() {
+            response = response.header(CONTENT_ENCODING, coding);
+        }
+        response
+            .body(body::boxed(Full::from(self.body(encoding))))
+            .unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn accepting(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT_ENCODING, value.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn negotiates_encoding() {
+        assert_eq!(Encoding::negotiate(&HeaderMap::new()), Encoding::Identity);
+        assert_eq!(
+            Encoding::negotiate(&accepting("gzip, deflate, br")),
+            Encoding::Brotli
+        );
+        assert_eq!(Encoding::negotiate(&accepting("gzip")), Encoding::Gzip);
+        assert_eq!(
+            Encoding::negotiate(&accepting("br;q=0, gzip;q=0.5")),
+            Encoding::Gzip
+        );
+        assert_eq!(Encoding::negotiate(&accepting("*")), Encoding::Brotli);
+        assert_eq!(Encoding::negotiate(&accepting("*, br;q=0")), Encoding::Gzip);
+        assert_eq!(
+            Encoding::negotiate(&accepting("identity")),
+            Encoding::Identity
+        );
+    }
+
+    #[test]
+    fn compressed_copies_match() {
+        let html = "<h1>Why Doesn't My Code Work?</h1>".repeat(100);
+        let page = Page::html(html.clone(), None);
+
+        let mut gunzipped = String::new();
+        flate2::read::GzDecoder::new(&page.body(Encoding::Gzip)[..])
+            .read_to_string(&mut gunzipped)
+            .unwrap();
+        assert_eq!(gunzipped, html);
+
+        let mut unbrotlied = String::new();
+        brotli::Decompressor::new(&page.body(Encoding::Brotli)[..], 4096)
+            .read_to_string(&mut unbrotlied)
+            .unwrap();
+        assert_eq!(unbrotlied, html);
+    }
+
+    #[test]
+    fn conditional_requests() {
+        let modified = "2023-04-05T12:00:00Z".parse().unwrap();
+        let page = Page::html("<p>hello</p>".to_string(), Some(modified));
+        let full = page.respond(StatusCode::OK, &accepting("br"));
+        assert_eq!(full.status(), StatusCode::OK);
+        let etag = full.headers()[ETAG].to_str().unwrap().to_string();
+        assert!(etag.ends_with("-br\""));
+        assert_eq!(
+            full.headers()[LAST_MODIFIED],
+            "Wed, 05 Apr 2023 12:00:00 GMT"
+        );
+
+        let mut headers = accepting("gzip");
+        headers.insert(IF_NONE_MATCH, etag.parse().unwrap());
+        let cached = page.respond(StatusCode::OK, &headers);
+        assert_eq!(cached.status(), StatusCode::NOT_MODIFIED);
+        assert!(cached.headers()[ETAG]
+            .to_str()
+            .unwrap()
+            .ends_with("-gzip\""));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(IF_NONE_MATCH, "\"stale\"".parse().unwrap());
+        headers.insert(
+            IF_MODIFIED_SINCE,
+            "Wed, 05 Apr 2023 12:00:00 GMT".parse().unwrap(),
+        );
+        assert_eq!(
+            page.respond(StatusCode::OK, &headers).status(),
+            StatusCode::OK
+        );
+
+        let since = |date: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(IF_MODIFIED_SINCE, date.parse().unwrap());
+            page.respond(StatusCode::OK, &headers).status()
+        };
+        assert_eq!(
+            since("Wed, 05 Apr 2023 12:00:00 GMT"),
+            StatusCode::NOT_MODIFIED
+        );
+        assert_eq!(since("Wed, 05 Apr 2023 11:59:59 GMT"), StatusCode::OK);
+    }
+}
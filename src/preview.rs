@@ -0,0 +1,93 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Mints and checks the tokens on preview links. A token is only good for the
+/// one slug it was made for, and only until it expires, so handing someone a
+/// preview doesn't let them see any other drafts.
+pub struct PreviewKey {
+    key: Vec<u8>,
+}
+
+impl PreviewKey {
+    /// Derive the signing key from the configured secret, rather than using
+    /// it as is, so the secret can't be turned into anything but previews.
+    pub fn new(secret: &[u8]) -> Self {
+        let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC takes keys of any length");
+        mac.update(b"whydoesntmycodework preview links");
+        Self {
+            key: mac.finalize().into_bytes().to_vec(),
+        }
+    }
+
+    /// A token for previewing `slug` until `expires`.
+    pub fn mint(&self, slug: &str, expires: DateTime<Utc>) -> String {
+        let expires = expires.timestamp();
+        let signature = self.mac(slug, expires).finalize().into_bytes();
+        format!("{}.{}", expires, URL_SAFE_NO_PAD.encode(signature))
+    }
+
+    /// Whether `token` was minted by us for `slug` and hasn't expired yet.
+    pub fn verify(&self, slug: &str, token: &str, now: DateTime<Utc>) -> bool {
+        let Some((expires, signature)) = token.split_once('.') else {
+            return false;
+        };
+        let (Ok(expires), Ok(signature)) =
+            (expires.parse::<i64>(), URL_SAFE_NO_PAD.decode(signature))
+        else {
+            return false;
+        };
+        // verify_slice compares in constant time.
+        self.mac(slug, expires).verify_slice(&signature).is_ok()
+            && Utc
+                .timestamp_opt(expires, 0)
+                .single()
+                .is_some_and(|expires| now < expires)
+    }
+
+    fn mac(&self, slug: &str, expires: i64) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.key).expect("HMAC takes keys of any length");
+        mac.update(format!("{}\n{}", slug, expires).as_bytes());
+        mac
+    }
+}
+
+/// How long a preview link lasts when the admin API isn't told otherwise.
+pub fn default_lifetime() -> Duration {
+    Duration::days(7)
+}
+
+/// The longest the admin API will make a preview link last.
+pub fn max_lifetime() -> Duration {
+    Duration::days(90)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_are_tied_to_a_slug_and_expire() {
+        let key = PreviewKey::new(b"secret");
+        let now = Utc.with_ymd_and_hms(2023, 4, 1, 12, 0, 0).unwrap();
+        let token = key.mint("draft", now + Duration::hours(1));
+
+        assert!(key.verify("draft", &token, now));
+        assert!(!key.verify("other-draft", &token, now));
+        assert!(!key.verify("draft", &token, now + Duration::hours(2)));
+        assert!(!PreviewKey::new(b"another secret").verify("draft", &token, now));
+
+        // Pushing the expiry back breaks the signature.
+        let (_, signature) = token.split_once('.').unwrap();
+        let later = (now + Duration::days(30)).timestamp();
+        assert!(!key.verify("draft", &format!("{}.{}", later, signature), now));
+
+        assert!(!key.verify("draft", "", now));
+        assert!(!key.verify("draft", "garbage", now));
+        assert!(!key.verify("draft", "1.!!!", now));
+    }
+}
@@ -0,0 +1,169 @@
+use comrak::nodes::NodeValue;
+use comrak::nodes::{AstNode, NodeCode};
+use comrak::plugins::syntect::SyntectAdapter;
+use comrak::{
+    format_html_with_plugins, parse_document, Anchorizer, Arena, ComrakOptions, ComrakPlugins,
+};
+
+use crate::types::SiteSettings;
+
+/// A heading in a post, for building its table of contents.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Heading {
+    pub level: u8,
+    pub text: String,
+    /// The id comrak gives the heading, so it can be linked to.
+    pub anchor: String,
+}
+
+/// Everything needed to turn a post's markdown into HTML.
+/// This is built once at startup and shared by every load of the posts, so a
+/// reload renders exactly the same way the initial load did.
+pub struct Renderer {
+    options: ComrakOptions,
+    adapter: SyntectAdapter,
+}
+
+impl Renderer {
+    pub fn new(settings: &SiteSettings) -> Self {
+        let mut options = ComrakOptions::default();
+        options.extension.footnotes = true;
+        options.extension.strikethrough = true;
+        options.extension.table = true;
+        options.extension.autolink = true;
+        Self {
+            options,
+            adapter: SyntectAdapter::new(&settings.syntax_theme),
+        }
+    }
+
+    pub fn render(&self, content: &str) -> String {
+        self.render_post(content, None, false).0
+    }
+
+    /// Render some markdown, pointing any relative links and images at `base`.
+    /// Post bundles use this so their assets resolve to wherever the post is
+    /// served from, rather than wherever the post happens to be on disk.
+    /// With `toc` the headings get ids, and come back for a table of contents.
+    pub fn render_post(
+        &self,
+        content: &str,
+        base: Option<&str>,
+        toc: bool,
+    ) -> (String, Vec<Heading>) {
+        let mut options = self.options.clone();
+        if toc {
+            options.extension.header_ids = Some(String::new());
+        }
+        let arena = Arena::new();
+        let root = parse_document(&arena, content, &options);
+        if let Some(base) = base {
+            for node in root.descendants() {
+                if let NodeValue::Link(ref mut link) | NodeValue::Image(ref mut link) =
+                    node.data.borrow_mut().value
+                {
+                    if is_relative(&link.url) {
+                        let url = link.url.trim_start_matches("./");
+                        link.url = format!("{}{}", base, url);
+                    }
+                }
+            }
+        }
+        // The plugins only borrow the adapter, so they're cheap to put
+        // together per render rather than storing them alongside it.
+        let mut plugins = ComrakPlugins::default();
+        plugins.render.codefence_syntax_highlighter = Some(&self.adapter);
+        let mut html = Vec::new();
+        format_html_with_plugins(root, &options, &mut html, &plugins).unwrap();
+        let headings = match toc {
+            true => headings(root),
+            false => Vec::new(),
+        };
+        (String::from_utf8(html).unwrap(), headings)
+    }
+
+    /// Render one of several pieces of markdown that end up on the same page,
+    /// like the cells of a notebook. `anchorizer` is shared between them, so
+    /// a heading repeated in a later piece gets numbered the way comrak would
+    /// number it within a single document.
+    pub fn render_section(
+        &self,
+        content: &str,
+        toc: bool,
+        anchorizer: &mut Anchorizer,
+    ) -> (String, Vec<Heading>) {
+        let (html, mut headings) = self.render_post(content, None, toc);
+        let mut renumbered = String::with_capacity(html.len());
+        let mut rest = html.as_str();
+        for heading in headings.iter_mut() {
+            let anchor = anchorizer.anchorize(heading.text.clone());
+            let link = anchor_link(&heading.anchor);
+            if let Some(at) = rest.find(&link) {
+                renumbered.push_str(&rest[..at]);
+                renumbered.push_str(&anchor_link(&anchor));
+                rest = &rest[at + link.len()..];
+            }
+            heading.anchor = anchor;
+        }
+        renumbered.push_str(rest);
+        (renumbered, headings)
+    }
+}
+
+/// The link comrak puts at the start of a heading when it gives it an id.
+fn anchor_link(anchor: &str) -> String {
+    format!(
+        "<a href=\"#{0}\" aria-hidden=\"true\" class=\"anchor\" id=\"{0}\"></a>",
+        anchor
+    )
+}
+
+/// Every heading in a document, with the same ids comrak gives them.
+fn headings<'a>(root: &'a AstNode<'a>) -> Vec<Heading> {
+    let mut anchorizer = Anchorizer::new();
+    root.descendants()
+        .filter_map(|node| match node.data.borrow().value {
+            NodeValue::Heading(ref heading) => {
+                let mut text = String::new();
+                collect_text(node, &mut text);
+                Some(Heading {
+                    level: heading.level,
+                    anchor: anchorizer.anchorize(text.clone()),
+                    text,
+                })
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// The text of a node, the way comrak works it out for heading ids.
+fn collect_text<'a>(node: &'a AstNode<'a>, text: &mut String) {
+    match node.data.borrow().value {
+        NodeValue::Text(ref literal) | NodeValue::Code(NodeCode { ref literal, .. }) => {
+            text.push_str(literal)
+        }
+        NodeValue::LineBreak | NodeValue::SoftBreak => text.push(' '),
+        _ => {
+            for child in node.children() {
+                collect_text(child, text);
+            }
+        }
+    }
+}
+
+/// Wrap code in a fence long enough that nothing inside it can close it early.
+pub fn fenced(code: &str, language: &str) -> String {
+    let longest_run = code.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    let fence = "`".repeat(longest_run.max(2) + 1);
+    format!("{}{}\n{}\n{}\n", fence, language, code, fence)
+}
+
+/// Whether a URL points somewhere relative to the current page, rather than to
+/// another site, the site root or an anchor on the same page.
+pub fn is_relative(url: &str) -> bool {
+    let has_scheme = url
+        .split_once(':')
+        .is_some_and(|(scheme, _)| !scheme.contains('/'));
+    !(url.is_empty() || url.starts_with(['/', '#', '?']) || has_scheme)
+}
@@ -0,0 +1,62 @@
+use crate::types::{Clock, State};
+use chrono::{DateTime, Utc};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+use tokio::sync::watch::Receiver;
+use tracing::*;
+
+/// The longest we sleep in one go. Timers run on the monotonic clock while
+/// publish dates are wall clock time, so waking up now and then keeps a
+/// change to the system clock from leaving a post waiting for too long.
+const MAX_SLEEP: Duration = Duration::from_secs(60 * 60);
+
+/// Rebuild `state` whenever a scheduled post is due, so it goes out on the
+/// listings, feeds, tag pages and its own page all at once. `next` carries
+/// when the next one is due, and is updated every time a snapshot is built.
+/// `clock` has to be the one `state` tells the time by.
+pub fn publish_on_schedule(
+    state: &Arc<State>,
+    next: Receiver<Option<DateTime<Utc>>>,
+    clock: Clock,
+) {
+    tokio::spawn(publish_when_due(Arc::downgrade(state), next, clock));
+}
+
+async fn publish_when_due(
+    state: Weak<State>,
+    mut next: Receiver<Option<DateTime<Utc>>>,
+    clock: Clock,
+) {
+    loop {
+        let due = *next.borrow_and_update();
+        let wait = async {
+            match due {
+                Some(due) => {
+                    let wait = (due - clock()).to_std().unwrap_or_default();
+                    tokio::time::sleep(wait.min(MAX_SLEEP)).await;
+                }
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            changed = next.changed() => {
+                // The state is gone, so there's nothing left to publish.
+                if changed.is_err() {
+                    break;
+                }
+            }
+            _ = wait => {
+                if due.is_some_and(|due| due > clock()) {
+                    continue;
+                }
+                match state.upgrade() {
+                    Some(state) => {
+                        info!("A scheduled post is due, rebuilding");
+                        state.reload().await;
+                    }
+                    None => break,
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use chrono::{DateTime, Utc};
+use quick_xml::events::{BytesDecl, Event};
+use quick_xml::{Result, Writer};
+
+use crate::feeds::{text, xml, Channel, SITE_URL};
+use crate::types::Post;
+
+const SITEMAP_NS: &str = "http://www.sitemaps.org/schemas/sitemap/0.9";
+
+pub const CONTENT_TYPE: &str = "application/xml";
+
+/// Build the sitemap. `posts` is every listed post and `tags` every tag
+/// page along with the listed posts on it.
+pub fn sitemap<'a>(
+    posts: &[&Post],
+    tags: impl Iterator<Item = (&'a str, Vec<&'a Post>)>,
+) -> String {
+    xml(|writer| write_sitemap(writer, posts, tags))
+}
+
+/// robots.txt, pointing crawlers at the sitemap.
+pub fn robots() -> String {
+    format!(
+        "User-agent: *\nDisallow: /metrics\nDisallow: /preview/\n\nSitemap: {}/sitemap.xml\n",
+        SITE_URL
+    )
+}
+
+fn write_sitemap<'a>(
+    writer: &mut Writer<Vec<u8>>,
+    posts: &[&Post],
+    tags: impl Iterator<Item = (&'a str, Vec<&'a Post>)>,
+) -> Result<()> {
+    let newest = posts.iter().map(|post| post.updated_at()).max();
+    let mut tags: Vec<_> = tags.collect();
+    // Tags come out of a HashMap, sort them so the sitemap only changes when
+    // the posts do.
+    tags.sort_by(|a, b| a.0.cmp(b.0));
+    writer.write_event(Event::Decl(BytesDecl::new("1.0", Some("UTF-8"), None)))?;
+    writer
+        .create_element("urlset")
+        .with_attribute(("xmlns", SITEMAP_NS))
+        .write_inner_content(|writer| {
+            url(writer, "/", newest)?;
+            url(writer, "/blog", newest)?;
+            url(writer, "/about", None)?;
+            url(writer, "/feeds", newest)?;
+            for post in posts.iter() {
+                let path = format!("/post/{}", post.frontmatter.slug);
+                url(writer, &path, Some(post.updated_at()))?;
+            }
+            for (tag, posts) in tags.iter() {
+                let newest = posts.iter().map(|post| post.updated_at()).max();
+                url(writer, &Channel::tag(tag).path, newest)?;
+            }
+            Ok(())
+        })?;
+    Ok(())
+}
+
+fn url(writer: &mut Writer<Vec<u8>>, path: &str, lastmod: Option<DateTime<Utc>>) -> Result<()> {
+    writer.create_element("url").write_inner_content(|writer| {
+        text(writer, "loc", &format!("{}{}", SITE_URL, path))?;
+        if let Some(lastmod) = lastmod {
+            text(writer, "lastmod", &lastmod.format("%Y-%m-%d").to_string())?;
+        }
+        Ok(())
+    })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::feeds::tests::{parse, post};
+    use chrono::NaiveDate;
+
+    #[test]
+    fn lists_every_page() {
+        let posts = [
+            post("New", "new", NaiveDate::from_ymd_opt(2023, 6, 1)),
+            post("Old", "old", None),
+        ];
+        let tags = vec![("rust", posts.iter().collect()), ("c&c", vec![&posts[1]])];
+        let urlset = parse(&sitemap(
+            &posts.iter().collect::<Vec<_>>(),
+            tags.into_iter(),
+        ));
+        assert_eq!(urlset.attributes["xmlns"], SITEMAP_NS);
+        let urls: Vec<_> = urlset
+            .all("url")
+            .iter()
+            .map(|u| {
+                let lastmod = u.all("lastmod").first().map(|l| l.text.clone());
+                (u.one("loc").text.clone(), lastmod)
+            })
+            .collect();
+        let at = |path: &str, date: Option<&str>| {
+            (
+                format!("https://whydoesntmycode.work{}", path),
+                date.map(str::to_string),
+            )
+        };
+        assert_eq!(
+            urls,
+            [
+                at("/", Some("2023-06-01")),
+                at("/blog", Some("2023-06-01")),
+                at("/about", None),
+                at("/feeds", Some("2023-06-01")),
+                at("/post/new", Some("2023-06-01")),
+                at("/post/old", Some("2023-04-05")),
+                at("/tag/c%26c", Some("2023-04-05")),
+                at("/tag/rust", Some("2023-06-01")),
+            ]
+        );
+    }
+}
@@ -0,0 +1,50 @@
+//! Setup shared between the tests of different modules.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use tempfile::TempDir;
+
+use crate::types::{SiteSettings, State};
+
+/// Posts covering every format and markdown extension we support.
+pub const FIXTURES: &str = "./tests/fixtures/posts";
+
+/// State built from the fixture posts, in debug mode so every one is served.
+pub fn fixture_state() -> Arc<State> {
+    state(Path::new(FIXTURES), true)
+}
+
+/// State built from whatever posts are in `dir`, with default settings.
+pub fn state(dir: &Path, debug: bool) -> Arc<State> {
+    let settings = SiteSettings {
+        posts_path: dir.to_path_buf(),
+        ..Default::default()
+    };
+    Arc::new(State::new(settings, "test".to_string(), debug))
+}
+
+/// A fresh directory holding the given files, each a path inside it and what
+/// goes in the file. Every call gets its own directory, which is deleted
+/// again when it's dropped.
+pub fn posts_dir<C: AsRef<[u8]>>(files: &[(&str, C)]) -> TempDir {
+    let dir = tempfile::Builder::new()
+        .prefix("whydoesntmycodework-")
+        .tempdir()
+        .unwrap();
+    for (file, content) in files {
+        let path = dir.path().join(file);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    }
+    dir
+}
+
+/// A post titled after its slug, tagged `test`, with nothing after the
+/// frontmatter.
+pub fn post(slug: &str, published: &str, visibility: &str) -> String {
+    format!(
+        "---\ntitle: {0}\nslug: {0}\npublished: {1}\ntags: [test]\nvisibility: {2}\n---\n",
+        slug, published, visibility
+    )
+}
@@ -1,20 +1,87 @@
-use crate::types::{Post, State};
-use maud::{html, Markup, PreEscaped, DOCTYPE};
-use std::sync::Arc;
+use crate::feeds::Channel;
+use crate::types::{Post, PostDiagnostic, Visibility};
+use chrono::{DateTime, Utc};
+use maud::{html, Markup, DOCTYPE};
+
+// What goes in a page's <head>, beyond what every page gets.
+#[derive(Default)]
+struct Head<'a> {
+    title: Option<&'a str>,
+    lang: Option<&'a str>,
+    description: Option<&'a str>,
+    author: Option<&'a str>,
+    canonical: Option<String>,
+    image: Option<String>,
+    // Keep search engines off pages that aren't meant to be found.
+    noindex: bool,
+    // Feeds of the page's own, advertised alongside the site's.
+    feeds: Vec<Channel>,
+}
+
+impl<'a> Head<'a> {
+    fn post(post: &'a Post) -> Self {
+        Head {
+            title: Some(&post.frontmatter.title),
+            lang: post.frontmatter.lang.as_deref(),
+            description: post.frontmatter.description.as_deref(),
+            author: Some(post.author()),
+            canonical: Some(post.canonical_url()),
+            image: post.cover_image_url(),
+            noindex: post.frontmatter.visibility != Visibility::Public,
+            feeds: Vec::new(),
+        }
+    }
+}
 
 // Eventually everything reaches this. This is our base template.
 // We keep everything nice and consistent by puting all our CSS and
 // stuff into here.
 fn base(title: Option<&str>, content: Markup) -> Markup {
+    base_with_head(
+        Head {
+            title,
+            ..Default::default()
+        },
+        content,
+    )
+}
+
+// Like base, but for pages with more to say about themselves.
+fn base_with_head(head: Head, content: Markup) -> Markup {
     html! {
         (DOCTYPE)
-        html lang = "en" {
+        html lang = (head.lang.unwrap_or("en")) {
             head {
                 meta charset="utf-8";
+                @if head.noindex {
+                    meta name="robots" content="noindex";
+                }
                 link rel="stylesheet" href={"/static/css/debugdance.css"};
                 link rel="stylesheet" href={"https://fonts.googleapis.com/css2?family=Fira+Code&family=Fira+Sans&display=swap"};
+                (feed_links(&Channel::site()))
+                @for channel in head.feeds.iter() {
+                    (feed_links(channel))
+                }
+                @if let Some(description) = head.description {
+                    meta name="description" content=(description);
+                    meta property="og:description" content=(description);
+                }
+                @if let Some(author) = head.author {
+                    meta name="author" content=(author);
+                }
+                @if let Some(canonical) = &head.canonical {
+                    link rel="canonical" href=(canonical);
+                    meta property="og:url" content=(canonical);
+                }
+                @if let Some(image) = &head.image {
+                    meta property="og:image" content=(image);
+                    meta name="twitter:card" content="summary_large_image";
+                }
+                @if let Some(title) = head.title {
+                    meta property="og:title" content=(title);
+                }
                 title {
-                    @if let Some(title) = title {
+                    @if let Some(title) = head.title {
                         (title) " - Why Doesnt My Code Work?"
                     } @else {
                         "Why Doesnt My Code Work?"
@@ -27,6 +94,15 @@ fn base(title: Option<&str>, content: Markup) -> Markup {
     }
 }
 
+// Autodiscovery links, so readers can find the feeds from a page's address.
+fn feed_links(channel: &Channel) -> Markup {
+    html! {
+        link rel="alternate" type="application/atom+xml" title=(format!("{} (Atom)", channel.title)) href=(format!("{}/atom.xml", channel.path));
+        link rel="alternate" type="application/rss+xml" title=(format!("{} (RSS)", channel.title)) href=(format!("{}/rss.xml", channel.path));
+        link rel="alternate" type="application/feed+json" title=(format!("{} (JSON Feed)", channel.title)) href=(format!("{}/feed.json", channel.path));
+    }
+}
+
 fn navbar() -> Markup {
     html! {
         nav {
@@ -47,68 +123,136 @@ fn blogpost_banner(post: &Post) -> Markup {
             h1 class="title" { (post.frontmatter.title) }
             div class="taglist" {
                 @for tag in post.frontmatter.tags.iter() {
-                    a class="taglist-tag" href = (format!("/tag/{}", tag)) {(format!("#{} ", tag))}
+                    a class="taglist-tag" href = (Channel::tag(tag).path) {(format!("#{} ", tag))}
                 }
             }
+            @if let Some(author) = &post.frontmatter.author {
+                small class="author" { "By " (author) } br;
+            }
             small class="time-to-read" { ({format!("Time to read: {}m", post.readtime)}) }
         }
     }
 }
 
-pub async fn render_blogpost(post: &Post) -> Markup {
+fn table_of_contents(post: &Post) -> Markup {
+    html! {
+        @if !post.toc.is_empty() {
+            nav class="toc" {
+                strong { "Contents" }
+                ul {
+                    @for heading in post.toc.iter() {
+                        li class=(format!("toc-h{}", heading.level)) {
+                            a href=(format!("#{}", heading.anchor)) { (heading.text) }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Only shown in debug mode, for things that would keep a post from being
+// published in production.
+fn problems_banner(problems: &[PostDiagnostic]) -> Markup {
+    html! {
+        @if !problems.is_empty() {
+            div class="debug-banner" {
+                strong { "This post won't be published until these are fixed:" }
+                ul {
+                    @for problem in problems.iter() {
+                        li { (problem.to_string()) }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn draft_banner() -> Markup {
+    html! {
+        div class="draft-banner" {
+            strong { "DRAFT" }
+            " This is a preview, the post isn't published yet and may still change. Please don't share the link."
+        }
+    }
+}
+
+// Drafts are only ever seen through preview links, so they get a banner
+// saying as much and are kept out of search engines.
+pub fn render_blogpost(post: &Post, problems: &[PostDiagnostic], draft: bool) -> Markup {
     let content = html! {
+        @if draft {
+            (draft_banner())
+        }
+        (problems_banner(problems))
         (blogpost_banner(post))
+        (table_of_contents(post))
         div class="blogpost-body" {
             (maud::PreEscaped(post.rendered.clone()))
         }
     };
-    base(Some(&post.frontmatter.title), content)
+    let mut head = Head::post(post);
+    head.noindex |= draft;
+    base_with_head(head, content)
 }
 
-pub async fn render_postlist(state: Arc<State>) -> Markup {
-    let content = render_list_of_posts(
-        state.posts.read().await.to_vec(),
-        "All Posts".to_string(),
-        state.debug_mode,
-    )
-    .await;
+pub fn render_postlist(posts: Vec<&Post>, debug: bool, now: DateTime<Utc>) -> Markup {
+    let content = render_list_of_posts(posts, "All Posts".to_string(), debug, now);
     base(Some("All posts"), content)
 }
 
-pub async fn render_tagged_post_list(state: Arc<State>, tag: String) -> Result<Markup, Markup> {
-    let filtered: Vec<Post> = state
-        .posts
-        .read()
-        .await
-        .iter()
-        .cloned()
-        .filter(|x| x.frontmatter.tags.contains(&tag))
-        .collect();
-    if filtered.is_empty() {
-        let error_page = html! {
-            p {(format!("No posts found with the tag #{}", tag))}
-        };
-        return Err(error_page);
+pub fn render_tagged_post_list(
+    posts: Vec<&Post>,
+    tag: &str,
+    debug: bool,
+    now: DateTime<Utc>,
+) -> Result<Markup, Markup> {
+    if posts.is_empty() {
+        return Err(render_unknown_tag(tag));
     }
     let heading = format!("Posts tagged with #{}", tag);
-    let body = render_list_of_posts(filtered, heading.clone(), state.debug_mode).await;
-    let final_content = base(Some(&heading.to_string()), body);
+    let body = render_list_of_posts(posts, heading.clone(), debug, now);
+    let head = Head {
+        title: Some(&heading),
+        feeds: vec![Channel::tag(tag)],
+        ..Default::default()
+    };
+    let final_content = base_with_head(head, body);
     Ok(final_content)
 }
 
-pub async fn render_list_of_posts(posts: Vec<Post>, heading: String, debug: bool) -> Markup {
+/// What's shown for a tag no post has.
+pub fn render_unknown_tag(tag: &str) -> Markup {
+    html! {
+        p {(format!("No posts found with the tag #{}", tag))}
+    }
+}
+
+pub fn render_list_of_posts(
+    posts: Vec<&Post>,
+    heading: String,
+    debug: bool,
+    now: DateTime<Utc>,
+) -> Markup {
     let content = html! {
         h1{(heading)}
         ul class="post-list" {
             @for post in posts.iter() {
-                @if post.frontmatter.published <= chrono::Utc::now().date_naive() && post.frontmatter.public {
+                @if post.is_listed(now) {
                     li class = "post-link" {
-                        span class="date" { {(post.frontmatter.published.format("Y%Y M%m D%d"))} " -- " }
+                        span class="date" { {(post.frontmatter.published.date().format("Y%Y M%m D%d"))} " -- " }
                         a href = ({format!("/post/{}", post.frontmatter.slug)}) {(post.frontmatter.title)}
+                        @if let Some(description) = &post.frontmatter.description {
+                            p class="post-description" { (description) }
+                        }
                     }
                 } @else if std::env::var("SITE_DEBUG").is_ok() || debug {
                     li class = "post-link" {
-                        span class="date" { "UNPUBLISHED -- " }
+                        @if post.frontmatter.visibility == Visibility::Unlisted {
+                            span class="date" { "UNLISTED -- " }
+                        } @else {
+                            span class="date" { "UNPUBLISHED -- " }
+                        }
                         a href = ({format!("/post/{}", post.frontmatter.slug)}) {(post.frontmatter.title)}
                     }
                 }
@@ -165,6 +309,8 @@ pub async fn render_feeds() -> Markup {
         h1{"Feeds"}
         ul {
             li{a href="https://whydoesntmycode.work/feeds/atom.xml" {"Atom" }};
+            li{a href="https://whydoesntmycode.work/feeds/rss.xml" {"RSS" }};
+            li{a href="https://whydoesntmycode.work/feeds/feed.json" {"JSON Feed" }};
         }
     };
     base(Some("Feeds"), content)
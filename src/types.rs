@@ -1,41 +1,89 @@
-use chrono::{NaiveDate, NaiveDateTime};
-use std::cmp::Ordering;
-use std::path::PathBuf;
-use tokio::sync::RwLock;
-
-use comrak::plugins::syntect::SyntectAdapter;
-use comrak::{markdown_to_html_with_plugins, ComrakOptions, ComrakPlugins};
-use serde::Deserialize;
+use notify::RecommendedWatcher;
+use std::cmp::{Ordering, Reverse};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use arc_swap::ArcSwap;
+use axum::body::Bytes;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
+use tokio::sync::watch;
 use tracing::*;
 
-const POST_BASE: &str = "https://whydoesntmycode.work/post/";
-const ATOM_HEADER: &str = "<?xml version='1.0' encoding='UTF-8'?>
-<feed xmlns=\"http://www.w3.org/2005/Atom\">
-<id>https://whydoesntmycode.work/blog.atom</id>
-<title>Why Doesn't My Code Work?</title>
-<author>
-    <name>Freyja</name>
-    <email>rncwnd@whydoesntmycode.work</email>
-</author>
-<link href=\"https://whydoesntmycode.work/blog.atom\" rel=\"self\" />
-<generator uri=\"https://whydoesntmycode.work\" version=\"1.3.1.2\">whydoesntmycode.work</generator>";
+use crate::content::Content;
+use crate::feeds::{AUTHOR_NAME, SITE_URL};
+pub use crate::frontmatter::{FrontMatter, FrontMatterFormat, Visibility};
+use crate::notebook;
+use crate::org;
+use crate::preview::PreviewKey;
+use crate::render::{is_relative, Heading, Renderer};
+use crate::scheduler;
+use crate::watcher;
 
+const POST_BASE: &str = "https://whydoesntmycode.work/post/";
 #[derive(Debug)]
 pub struct SiteSettings {
     pub posts_path: PathBuf,
+    pub syntax_theme: String,
+    /// How many of the newest posts a feed holds before the rest get archived.
+    pub feed_length: usize,
+    /// What preview links are signed with. Without one, a random key is made
+    /// up at startup and links stop working when the server restarts.
+    pub preview_secret: Option<String>,
+    /// Where posts that only give a date were published from, so we know
+    /// which midnight they go out at.
+    pub timezone: Tz,
 }
 
 impl Default for SiteSettings {
     fn default() -> Self {
         Self {
             posts_path: "./posts".parse().unwrap(),
+            syntax_theme: "base16-eighties.dark".to_string(),
+            feed_length: 20,
+            preview_secret: None,
+            timezone: Tz::UTC,
         }
     }
 }
 
+impl SiteSettings {
+    /// The defaults, with anything set in the environment taking over.
+    pub fn from_env() -> Self {
+        let mut settings = SiteSettings::default();
+        match std::env::var("FEED_LENGTH").map(|n| n.parse::<usize>()) {
+            Ok(Ok(n)) if n > 0 => settings.feed_length = n,
+            Ok(_) => warn!(
+                "FEED_LENGTH should be a whole number above zero, using {}",
+                settings.feed_length
+            ),
+            Err(_) => {}
+        }
+        match std::env::var("SITE_TIMEZONE").map(|tz| tz.parse::<Tz>()) {
+            Ok(Ok(timezone)) => settings.timezone = timezone,
+            Ok(Err(_)) => warn!(
+                "SITE_TIMEZONE should be a timezone name like Europe/London, using {}",
+                settings.timezone
+            ),
+            Err(_) => {}
+        }
+        match std::env::var("PREVIEW_SECRET") {
+            Ok(secret) if !secret.is_empty() => settings.preview_secret = Some(secret),
+            _ => warn!("PREVIEW_SECRET isn't set, preview links won't survive a restart"),
+        }
+        settings
+    }
+}
+
 #[derive(Deserialize, Debug, Clone, Copy)]
 pub struct Ports {
+    #[allow(dead_code)]
     pub http: u32,
     pub https: u32,
 }
@@ -53,14 +101,106 @@ impl HostSettings {
     }
 }
 
-#[derive(Error, Debug)]
+#[derive(Error, Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
 pub enum PostParseError {
-    #[error("Frontmatter for a post was invalid")]
-    FrontmatterError,
+    #[error("{format} frontmatter for a post was invalid: {message}")]
+    FrontmatterError {
+        format: FrontMatterFormat,
+        message: String,
+        line: Option<usize>,
+        column: Option<usize>,
+    },
     #[error("There is no front matter!")]
     NoFrontmatter,
-    #[error("Only found one ---, yaml is probably unterminated!")]
-    UnterminatedFrontmatter,
+    #[error("Only found the start of the {format} frontmatter, it's probably unterminated!")]
+    UnterminatedFrontmatter { format: FrontMatterFormat },
+    #[error("Notebook was invalid: {message}")]
+    InvalidNotebook {
+        message: String,
+        line: Option<usize>,
+        column: Option<usize>,
+    },
+    #[error("Couldn't read file: {message}")]
+    Io { message: String },
+    #[error("File isn't valid UTF-8")]
+    NotUtf8,
+    #[error("Frontmatter has keys that aren't used for anything: {}", .keys.join(", "))]
+    UnknownKeys { keys: Vec<String> },
+    #[error("Found a directory with no index.md in it")]
+    NotAFile,
+    #[error("Slug \"{slug}\" is also used by {}", list_paths(.others))]
+    DuplicateSlug { slug: String, others: Vec<PathBuf> },
+}
+
+fn list_paths(paths: &[PathBuf]) -> String {
+    paths
+        .iter()
+        .map(|p| p.display().to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Whether a problem stops a post from being published.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    /// Worth fixing, but the post goes out anyway.
+    Warning,
+}
+
+/// Something that went wrong loading a post, and where.
+#[derive(Debug, Clone, Serialize)]
+pub struct PostDiagnostic {
+    pub path: PathBuf,
+    pub severity: Severity,
+    #[serde(flatten)]
+    pub error: PostParseError,
+}
+
+impl PostDiagnostic {
+    pub fn new(path: PathBuf, error: PostParseError) -> Self {
+        Self {
+            path,
+            severity: Severity::Error,
+            error,
+        }
+    }
+
+    pub fn warning(path: PathBuf, error: PostParseError) -> Self {
+        Self {
+            path,
+            severity: Severity::Warning,
+            error,
+        }
+    }
+}
+
+impl fmt::Display for PostDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.path.display())?;
+        if let PostParseError::FrontmatterError {
+            line: Some(line),
+            column,
+            ..
+        }
+        | PostParseError::InvalidNotebook {
+            line: Some(line),
+            column,
+            ..
+        } = &self.error
+        {
+            write!(f, ":{}", line)?;
+            if let Some(column) = column {
+                write!(f, ":{}", column)?;
+            }
+        }
+        if self.severity == Severity::Warning {
+            write!(f, ": warning")?;
+        }
+        write!(f, ": {}", self.error)
+    }
 }
 
 #[derive(PartialEq, Clone)]
@@ -68,19 +208,83 @@ pub struct Post {
     pub frontmatter: FrontMatter,
     pub rendered: String,
     pub readtime: u64,
+    /// The directory holding the post and its assets, if it's a bundle.
+    pub bundle: Option<PathBuf>,
+    /// When the post's file last changed on disk, if we could find out.
+    pub modified: Option<DateTime<Utc>>,
+    /// Headings for the table of contents, empty unless the post asks for one.
+    pub toc: Vec<Heading>,
+    /// The timezone dates without a time in the frontmatter are taken to be in.
+    pub timezone: Tz,
+    /// Files generated while rendering, such as notebook images, served
+    /// alongside the post the same way a bundle's assets are.
+    pub assets: HashMap<String, Bytes>,
 }
 
 impl Post {
-    fn new(
+    fn new(content: String, renderer: &Renderer) -> Result<Post, PostParseError> {
+        let (frontmatter, body) = FrontMatter::new(&content)?;
+        Ok(Post::from_markdown(frontmatter, body, renderer, None))
+    }
+
+    /// A bundle is a directory with an `index.md` and whatever it links to.
+    /// Relative links get pointed at where the bundle is served from, so they
+    /// follow the post's slug rather than where it lives on disk.
+    fn from_bundle(
         content: String,
-        co: &ComrakOptions,
-        cplug: &ComrakPlugins,
+        renderer: &Renderer,
+        dir: PathBuf,
     ) -> Result<Post, PostParseError> {
-        let frontmatter = FrontMatter::new(&content)?;
-        //let rendered = markdown_to_html(&content, co);
-        let rendered = markdown_to_html_with_plugins(&content, co, cplug);
-        let readtime = estimated_read_time::text(
-            &content,
+        let (frontmatter, body) = FrontMatter::new(&content)?;
+        Ok(Post::from_markdown(frontmatter, body, renderer, Some(dir)))
+    }
+
+    /// Org posts get translated into markdown, then rendered like any other.
+    fn from_org(content: String, renderer: &Renderer) -> Result<Post, PostParseError> {
+        let (frontmatter, body) = org::parse(&content)?;
+        Ok(Post::from_markdown(frontmatter, &body, renderer, None))
+    }
+
+    fn from_notebook(content: String, renderer: &Renderer) -> Result<Post, PostParseError> {
+        let notebook = notebook::render(&content, renderer)?;
+        Ok(Post {
+            frontmatter: notebook.frontmatter,
+            rendered: notebook.rendered,
+            readtime: Post::read_time(&notebook.source),
+            bundle: None,
+            modified: None,
+            toc: notebook.toc,
+            timezone: Tz::UTC,
+            assets: notebook.assets,
+        })
+    }
+
+    fn from_markdown(
+        frontmatter: FrontMatter,
+        body: &str,
+        renderer: &Renderer,
+        bundle: Option<PathBuf>,
+    ) -> Post {
+        let base = bundle
+            .as_ref()
+            .map(|_| format!("/post/{}/", frontmatter.slug));
+        let (rendered, toc) = renderer.render_post(body, base.as_deref(), frontmatter.toc);
+        Post {
+            rendered,
+            readtime: Post::read_time(body),
+            frontmatter,
+            bundle,
+            modified: None,
+            toc,
+            timezone: Tz::UTC,
+            assets: HashMap::new(),
+        }
+    }
+
+    /// Estimated time to read, in minutes.
+    fn read_time(text: &str) -> u64 {
+        estimated_read_time::text(
+            text,
             &estimated_read_time::Options::new()
                 .technical_document(true)
                 .technical_difficulty(2)
@@ -88,204 +292,791 @@ impl Post {
                 .unwrap_or_default(),
         )
         .seconds()
-            / 60;
-        Ok(Post {
-            frontmatter,
-            rendered,
-            readtime,
-        })
+            / 60
+    }
+
+    /// When the post goes out. Posts are sorted and scheduled by this.
+    pub fn published_at(&self) -> DateTime<Utc> {
+        self.frontmatter.published.in_timezone(self.timezone)
     }
 
-    fn get_full_url(&self) -> String {
+    /// When the post was last updated, which is when it was published if it
+    /// never has been.
+    pub fn updated_at(&self) -> DateTime<Utc> {
+        match self.frontmatter.updated {
+            Some(updated) => updated.in_timezone(self.timezone),
+            None => self.published_at(),
+        }
+    }
+
+    /// The later of the post's own dates and when its file was last touched,
+    /// so an edit that forgets to bump `updated` still counts.
+    pub fn last_modified(&self) -> DateTime<Utc> {
+        let date = self.updated_at();
+        self.modified.map_or(date, |modified| modified.max(date))
+    }
+
+    /// Whether the post is out as of `now` and can be read by anyone with its
+    /// URL.
+    pub fn is_served(&self, now: DateTime<Utc>) -> bool {
+        self.frontmatter.visibility != Visibility::Private && self.published_at() <= now
+    }
+
+    /// Whether the post is out as of `now` and public, so belongs in listings,
+    /// feeds and the sitemap.
+    pub fn is_listed(&self, now: DateTime<Utc>) -> bool {
+        self.frontmatter.visibility == Visibility::Public && self.published_at() <= now
+    }
+
+    pub fn get_full_url(&self) -> String {
         format!("{}{}", POST_BASE, self.frontmatter.slug)
     }
 
-    fn as_atom(&self) -> String {
-        format!(
-            "
-<entry>
-    <id>{}</id>
-    <title>{}</title>
-    <published>{}</published>
-    <updated>{}</updated>
-    <content type=\"html\" xml:base=\"{}\"><![CDATA[{}]]> </content>
-    <link href=\"{}\" rel=\"alternate\" />
-</entry>",
-            self.get_full_url(),
-            self.frontmatter.title,
-            self.frontmatter.published,
-            self.frontmatter.updated.unwrap_or_default(),
-            self.get_full_url(),
-            self.rendered,
-            self.get_full_url()
-        )
+    /// Where search engines should consider the post to live.
+    pub fn canonical_url(&self) -> String {
+        self.frontmatter
+            .canonical_url
+            .clone()
+            .unwrap_or_else(|| self.get_full_url())
+    }
+
+    pub fn author(&self) -> &str {
+        self.frontmatter.author.as_deref().unwrap_or(AUTHOR_NAME)
+    }
+
+    /// The cover image as a full URL, since link previews and feeds are read
+    /// from elsewhere. Relative paths are taken to be inside the post's bundle.
+    pub fn cover_image_url(&self) -> Option<String> {
+        let image = self.frontmatter.cover_image.as_deref()?;
+        Some(if image.starts_with('/') {
+            format!("{}{}", SITE_URL, image)
+        } else if is_relative(image) {
+            format!("{}/{}", self.get_full_url(), image.trim_start_matches("./"))
+        } else {
+            image.to_string()
+        })
     }
 }
 
 impl PartialOrd for Post {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.frontmatter.published.cmp(&other.frontmatter.published))
+        Some(self.published_at().cmp(&other.published_at()))
     }
 }
 
-#[derive(Deserialize, PartialEq, Clone)]
-pub struct FrontMatter {
-    pub title: String,
-    pub slug: String,
-    pub published: chrono::NaiveDate,
-    pub updated: Option<chrono::NaiveDate>,
-    pub tags: Vec<String>,
-    pub public: bool,
+/// A rendered post, along with a hash of the file it was rendered from so a
+/// reload can tell whether it needs rendering again.
+struct CachedPost {
+    hash: u64,
+    post: Post,
 }
 
-impl FrontMatter {
-    fn new(content: &str) -> Result<FrontMatter, PostParseError> {
-        let matches: Vec<_> = content.match_indices("---").collect();
-        if matches.is_empty() {
-            Err(PostParseError::NoFrontmatter)
-        } else if matches.len() == 1 {
-            Err(PostParseError::UnterminatedFrontmatter)
-        } else {
-            let start = (matches[0].0) + 3; // Skip over the first 3 ---
-            let end = matches[1].0;
-            let slice = &content[start..end].to_string();
-            match serde_yaml::from_str(slice) {
-                Ok(x) => Ok(x),
-                Err(e) => {
-                    error!("{}", e);
-                    Err(PostParseError::FrontmatterError)
-                }
-            }
+/// Rendered posts keyed on the file they came from.
+type RenderCache = HashMap<PathBuf, CachedPost>;
+
+fn content_hash(content: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    content.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Find every post that shares its slug with another, pointing each one at
+/// the others it collides with. A post that's already out stays up when the
+/// only posts it collides with are drafts, so only the drafts get reported.
+fn duplicate_slugs(posts: &[(PathBuf, Post)], now: DateTime<Utc>) -> Vec<PostDiagnostic> {
+    let mut by_slug: HashMap<&str, Vec<(&PathBuf, &Post)>> = HashMap::new();
+    for (path, post) in posts.iter() {
+        by_slug
+            .entry(post.frontmatter.slug.as_str())
+            .or_default()
+            .push((path, post));
+    }
+    let mut diagnostics = Vec::new();
+    for (slug, posts) in by_slug.into_iter().filter(|(_, posts)| posts.len() > 1) {
+        let mut served = posts.iter().filter(|(_, post)| post.is_served(now));
+        let live = match (served.next(), served.next()) {
+            (Some((path, _)), None) => Some(*path),
+            _ => None,
+        };
+        for (path, _) in posts.iter().filter(|(path, _)| Some(*path) != live) {
+            let mut others: Vec<PathBuf> = posts
+                .iter()
+                .filter(|(other, _)| other != path)
+                .map(|(other, _)| other.to_path_buf())
+                .collect();
+            others.sort();
+            diagnostics.push(PostDiagnostic::new(
+                path.to_path_buf(),
+                PostParseError::DuplicateSlug {
+                    slug: slug.to_string(),
+                    others,
+                },
+            ));
         }
     }
+    diagnostics.sort_by(|a, b| a.path.cmp(&b.path));
+    diagnostics
 }
 
+/// Where the time comes from when deciding which posts are out.
+pub type Clock = Arc<dyn Fn() -> DateTime<Utc> + Send + Sync>;
+
 pub struct State {
-    pub posts: RwLock<Vec<Post>>,
+    content: ArcSwap<Content>,
     pub admin_token: String,
-    pub atom_feed: RwLock<String>,
+    pub preview_key: PreviewKey,
     pub debug_mode: bool,
     posts_path: PathBuf,
+    feed_length: usize,
+    timezone: Tz,
+    /// When the next scheduled post is due, as of the current snapshot.
+    next_publication: watch::Sender<Option<DateTime<Utc>>>,
+    renderer: Renderer,
+    watcher: Mutex<Option<RecommendedWatcher>>,
+    /// Held for the length of a reload, so only one happens at a time.
+    reloading: tokio::sync::Mutex<()>,
+    render_cache: Mutex<RenderCache>,
+    clock: Clock,
 }
 
 impl State {
+    /// Read a single post file, reusing the cached render if the file hasn't
+    /// changed since we last saw it.
+    fn load_post(
+        path: &Path,
+        renderer: &Renderer,
+        timezone: Tz,
+        cache: &mut RenderCache,
+        fresh: &mut RenderCache,
+    ) -> Result<Post, PostParseError> {
+        let (file, bundle) = match path.is_dir() {
+            true => (path.join("index.md"), Some(path.to_path_buf())),
+            false => (path.to_path_buf(), None),
+        };
+        if bundle.is_some() && !file.is_file() {
+            return Err(PostParseError::NotAFile);
+        }
+        let bytes = std::fs::read(&file).map_err(|e| PostParseError::Io {
+            message: e.to_string(),
+        })?;
+        let content = String::from_utf8(bytes).map_err(|_| PostParseError::NotUtf8)?;
+        let hash = content_hash(&content);
+        let mut post = match cache.remove(path) {
+            Some(cached) if cached.hash == hash => {
+                trace!("{:?} is unchanged, reusing the previous render", path);
+                cached.post
+            }
+            _ => match (bundle, path.extension().and_then(|e| e.to_str())) {
+                (Some(dir), _) => Post::from_bundle(content, renderer, dir)?,
+                (None, Some("org")) => Post::from_org(content, renderer)?,
+                (None, Some("ipynb")) => Post::from_notebook(content, renderer)?,
+                (None, _) => Post::new(content, renderer)?,
+            },
+        };
+        post.timezone = timezone;
+        post.modified = std::fs::metadata(&file)
+            .and_then(|m| m.modified())
+            .ok()
+            .map(DateTime::from);
+        fresh.insert(
+            path.to_path_buf(),
+            CachedPost {
+                hash,
+                post: post.clone(),
+            },
+        );
+        Ok(post)
+    }
+
+    /// Load every post we can. Drafts are loaded too so they can be
+    /// previewed, it's up to `Content` to keep them off the site. Which posts
+    /// are out yet is as of `now`.
     fn get_posts(
         post_dir: Option<PathBuf>,
-        comrak_opts: &ComrakOptions,
-        comrak_plugins: &ComrakPlugins,
+        renderer: &Renderer,
+        timezone: Tz,
         debug_mode: bool,
-    ) -> Vec<Post> {
-        let mut v: Vec<Post> = Vec::new();
+        now: DateTime<Utc>,
+        cache: &mut RenderCache,
+    ) -> (Vec<Post>, Vec<PostDiagnostic>) {
+        let mut v: Vec<(PathBuf, Post)> = Vec::new();
+        let mut diagnostics: Vec<PostDiagnostic> = Vec::new();
+        // Only files we see on this pass make it into the new cache, so
+        // deleted posts don't linger in it forever.
+        let mut fresh = RenderCache::new();
         let p: PathBuf = post_dir.unwrap_or("./posts".parse().unwrap());
         trace!("Loading posts from {:?}", p);
-        let post_paths = std::fs::read_dir(p).unwrap();
+        let post_paths = match std::fs::read_dir(&p) {
+            Ok(paths) => paths,
+            Err(e) => {
+                let diagnostic = PostDiagnostic::new(
+                    p,
+                    PostParseError::Io {
+                        message: e.to_string(),
+                    },
+                );
+                error!("Couldn't read posts directory: {}", diagnostic);
+                *cache = fresh;
+                return (Vec::new(), vec![diagnostic]);
+            }
+        };
         for path in post_paths {
             let validpath = match path {
                 Ok(p) => p.path(),
                 Err(x) => {
-                    error!("Could not get valid path from file in posts dir. {}", x);
+                    let diagnostic = PostDiagnostic::new(
+                        p.clone(),
+                        PostParseError::Io {
+                            message: x.to_string(),
+                        },
+                    );
+                    error!(
+                        "Could not get valid path from file in posts dir. {}",
+                        diagnostic
+                    );
+                    diagnostics.push(diagnostic);
                     continue;
                 }
             };
 
-            // Now we have a valid file path that we can read the markdown from.
-            let filename = String::from(validpath.file_stem().unwrap().to_str().unwrap());
-            trace!("Loading post from {:?}", filename);
-            let content = std::fs::read_to_string(validpath).unwrap();
-            let post = Post::new(content, comrak_opts, comrak_plugins);
-            match post {
+            trace!("Loading post from {:?}", validpath);
+            match Self::load_post(&validpath, renderer, timezone, cache, &mut fresh) {
                 Ok(post) => {
-                    if debug_mode {
-                        v.push(post)
-                    } else {
-                        if post.frontmatter.public
-                            && post.frontmatter.published <= chrono::Utc::now().date_naive()
-                        {
-                            v.push(post)
-                        } else {
-                            info!("Post \"{}\" is either not due to be published, or not set to public Skipping.", post.frontmatter.title);
-                        }
+                    let unknown = post.frontmatter.unknown_keys();
+                    if !unknown.is_empty() {
+                        let diagnostic = PostDiagnostic::warning(
+                            validpath.clone(),
+                            PostParseError::UnknownKeys { keys: unknown },
+                        );
+                        warn!("{}", diagnostic);
+                        diagnostics.push(diagnostic);
                     }
+                    v.push((validpath, post))
                 }
                 Err(e) => {
-                    warn!("error {:?} on file {:?}, SKIPPING", e, filename);
-                    continue;
+                    let diagnostic = PostDiagnostic::new(validpath, e);
+                    warn!("{}, SKIPPING", diagnostic);
+                    diagnostics.push(diagnostic);
                 }
             }
         }
-        info!("Loaded {} posts", v.len());
-        v
+        *cache = fresh;
+
+        // Which of two posts with the same slug gets served would come down to
+        // read_dir order, so in production neither does, unless one is out
+        // and the rest are drafts. In debug we keep
+        // both so the collision can be shown on the page.
+        let duplicates = duplicate_slugs(&v, now);
+        for diagnostic in duplicates.iter() {
+            error!("{}", diagnostic);
+        }
+        let v: Vec<Post> = v
+            .into_iter()
+            .filter(|(path, _)| debug_mode || !duplicates.iter().any(|d| &d.path == path))
+            .map(|(_, post)| post)
+            .collect();
+        diagnostics.extend(duplicates);
+
+        info!(
+            "Loaded {} posts with {} problems",
+            v.len(),
+            diagnostics.len()
+        );
+        (v, diagnostics)
     }
 
     pub fn new(settings: SiteSettings, admin_token: String, debug: bool) -> Self {
-        let adapter = SyntectAdapter::new("base16-eighties.dark");
-        let mut comrak_opts = ComrakOptions::default();
-        comrak_opts.extension.front_matter_delimiter = Some("---".to_owned());
-        comrak_opts.extension.footnotes = true;
-        comrak_opts.extension.strikethrough = true;
-        comrak_opts.extension.table = true;
-        comrak_opts.extension.autolink = true;
-        let mut comrak_plugins = ComrakPlugins::default();
-        comrak_plugins.render.codefence_syntax_highlighter = Some(&adapter);
-        let mut posts = State::get_posts(
+        State::with_clock(settings, admin_token, debug, Arc::new(Utc::now))
+    }
+
+    /// Like `new`, but telling the time by `clock` rather than the system
+    /// clock.
+    pub fn with_clock(
+        settings: SiteSettings,
+        admin_token: String,
+        debug: bool,
+        clock: Clock,
+    ) -> Self {
+        let renderer = Renderer::new(&settings);
+        let mut render_cache = RenderCache::new();
+        let now = clock();
+        let (mut posts, diagnostics) = State::get_posts(
             Some(settings.posts_path.clone()),
-            &comrak_opts,
-            &comrak_plugins,
+            &renderer,
+            settings.timezone,
             debug,
+            now,
+            &mut render_cache,
         );
-        posts.sort_by(|a, b| b.frontmatter.published.cmp(&a.frontmatter.published));
-        let atom_feed = Self::initialize_atom_feed(posts.clone());
+        posts.sort_by_key(|p| Reverse(p.published_at()));
+        let preview_key = match &settings.preview_secret {
+            Some(secret) => PreviewKey::new(secret.as_bytes()),
+            None => PreviewKey::new(&rand::random::<[u8; 32]>()),
+        };
+        let content = Content::new(posts, diagnostics, debug, settings.feed_length, now);
+        let (next_publication, _) = watch::channel(content.next_publication());
         Self {
-            posts: RwLock::new(posts),
+            content: ArcSwap::from_pointee(content),
+            next_publication,
             posts_path: settings.posts_path,
-            atom_feed: RwLock::new(atom_feed),
+            feed_length: settings.feed_length,
+            timezone: settings.timezone,
+            renderer,
             admin_token,
+            preview_key,
             debug_mode: debug,
+            watcher: Mutex::new(None),
+            reloading: tokio::sync::Mutex::new(()),
+            render_cache: Mutex::new(render_cache),
+            clock,
         }
     }
 
-    pub fn generate_posts(&self) -> Vec<Post> {
-        let adapter = SyntectAdapter::new("base16-eighties.dark");
-        let mut comrak_opts = ComrakOptions::default();
-        comrak_opts.extension.front_matter_delimiter = Some("---".to_owned());
-        let mut comrak_plugins = ComrakPlugins::default();
-        comrak_plugins.render.codefence_syntax_highlighter = Some(&adapter);
-        let mut posts = State::get_posts(
+    /// The current snapshot of the site's content.
+    /// Hang on to it for as long as a request needs to, a reload won't
+    /// change it out from under you.
+    pub fn content(&self) -> Arc<Content> {
+        self.content.load_full()
+    }
+
+    /// Start watching the posts directory so edits show up without anyone
+    /// having to hit the reload endpoint. If the watcher can't be set up we
+    /// carry on without it, the admin API still works.
+    pub fn watch_posts(self: &Arc<Self>) {
+        match watcher::watch_posts(self, &self.posts_path) {
+            Ok(w) => *self.watcher.lock().unwrap() = Some(w),
+            Err(e) => error!(
+                "Couldn't watch {:?} for changes, posts will only reload via the admin API. {:?}",
+                self.posts_path, e
+            ),
+        }
+    }
+
+    /// Build a fresh snapshot whenever a post that's been scheduled for later
+    /// is due, so nobody has to be around to reload when it goes out.
+    pub fn publish_on_schedule(self: &Arc<Self>) {
+        scheduler::publish_on_schedule(
+            self,
+            self.next_publication.subscribe(),
+            Arc::clone(&self.clock),
+        );
+    }
+
+    /// Re-read every post from disk and swap in a fresh snapshot built from
+    /// them. Returns whatever problems were found along the way.
+    /// Rendering is CPU bound, so it happens off the async runtime. Reloads
+    /// take turns, otherwise a slow one could finish last and put an older
+    /// snapshot back over a newer one.
+    pub async fn reload(self: &Arc<Self>) -> Vec<PostDiagnostic> {
+        let _turn = self.reloading.lock().await;
+        let state = Arc::clone(self);
+        tokio::task::spawn_blocking(move || {
+            let now = (state.clock)();
+            let (newposts, diagnostics) = state.generate_posts(now);
+            let content = Content::new(
+                newposts,
+                diagnostics.clone(),
+                state.debug_mode,
+                state.feed_length,
+                now,
+            );
+            let next_publication = content.next_publication();
+            state.content.store(Arc::new(content));
+            state.next_publication.send_replace(next_publication);
+            diagnostics
+        })
+        .await
+        .expect("Building a snapshot panicked")
+    }
+
+    pub fn generate_posts(&self, now: DateTime<Utc>) -> (Vec<Post>, Vec<PostDiagnostic>) {
+        let (mut posts, diagnostics) = State::get_posts(
             Some(self.posts_path.clone()),
-            &comrak_opts,
-            &comrak_plugins,
+            &self.renderer,
+            self.timezone,
             self.debug_mode,
+            now,
+            &mut self.render_cache.lock().unwrap(),
         );
-        posts.sort_by(|a, b| b.frontmatter.published.cmp(&a.frontmatter.published));
-        posts
+        posts.sort_by_key(|p| Reverse(p.published_at()));
+        (posts, diagnostics)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pages::{Encoding, Page};
+    use axum::http::header::LAST_MODIFIED;
+    use axum::http::{HeaderMap, StatusCode};
+
+    use crate::testing::{fixture_state, post, posts_dir};
+
+    /// Load the posts in `dir` the way a reload would.
+    fn load(dir: &Path, debug: bool, now: DateTime<Utc>) -> (Vec<Post>, Vec<PostDiagnostic>) {
+        let renderer = Renderer::new(&SiteSettings::default());
+        let dir = Some(dir.to_path_buf());
+        State::get_posts(dir, &renderer, Tz::UTC, debug, now, &mut RenderCache::new())
     }
 
-    pub async fn generate_atom_feed(&self) -> String {
-        //let rss_entries: Vec<String> = Vec::new();
-        let mut feed = format!("{}", ATOM_HEADER);
-        let rss_entries: Vec<String> = self
+    async fn rendered(state: &State) -> Vec<(String, String)> {
+        state
+            .content()
             .posts
-            .read()
+            .iter()
+            .map(|p| (p.frontmatter.slug.clone(), p.rendered.clone()))
+            .collect()
+    }
+
+    async fn rendered_slug(state: &State, slug: &str) -> String {
+        rendered(state)
             .await
+            .into_iter()
+            .find(|(s, _)| s == slug)
+            .map(|(_, r)| r)
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn reload_renders_the_same_as_startup() {
+        let state = fixture_state();
+        let before = rendered(&state).await;
+        assert_eq!(before.len(), 6);
+
+        // Make sure the reload actually re-renders, rather than handing us
+        // back what the initial load put in the cache.
+        state.render_cache.lock().unwrap().clear();
+        state.reload().await;
+
+        assert_eq!(before, rendered(&state).await);
+    }
+
+    #[tokio::test]
+    async fn reloads_take_turns() {
+        let state = fixture_state();
+        let before = rendered(&state).await;
+        let reloads: Vec<_> = (0..4)
+            .map(|_| {
+                let state = Arc::clone(&state);
+                tokio::spawn(async move { state.reload().await })
+            })
+            .collect();
+        for reload in reloads {
+            reload.await.unwrap();
+        }
+        assert!(state.reloading.try_lock().is_ok());
+        assert_eq!(before, rendered(&state).await);
+    }
+
+    #[test]
+    fn every_diagnostic_serializes() {
+        let errors = [
+            PostParseError::FrontmatterError {
+                format: FrontMatterFormat::Yaml,
+                message: "bad".to_string(),
+                line: Some(1),
+                column: None,
+            },
+            PostParseError::NoFrontmatter,
+            PostParseError::UnterminatedFrontmatter {
+                format: FrontMatterFormat::Toml,
+            },
+            PostParseError::InvalidNotebook {
+                message: "bad".to_string(),
+                line: None,
+                column: None,
+            },
+            PostParseError::Io {
+                message: "denied".to_string(),
+            },
+            PostParseError::NotUtf8,
+            PostParseError::UnknownKeys {
+                keys: vec!["started".to_string()],
+            },
+            PostParseError::NotAFile,
+            PostParseError::DuplicateSlug {
+                slug: "s".to_string(),
+                others: vec!["b.md".into()],
+            },
+        ];
+        for error in errors {
+            let diagnostic = PostDiagnostic::new("a.md".into(), error);
+            let json = serde_json::to_value(&diagnostic).unwrap();
+            assert_eq!(json["path"], "a.md");
+            assert!(json["kind"].is_string());
+        }
+    }
+
+    #[test]
+    fn unreadable_posts_are_reported() {
+        let dir = posts_dir(&[("latin1.md", b"---\ntitle: caf\xe9\n---\n")]);
+        std::fs::create_dir_all(dir.path().join("nested").join("deeper")).unwrap();
+
+        let (posts, diagnostics) = load(dir.path(), false, Utc::now());
+        assert!(posts.is_empty());
+        let mut kinds: Vec<_> = diagnostics
             .iter()
-            .map(|x| x.as_atom())
+            .map(|d| serde_json::to_value(d).unwrap()["kind"].clone())
             .collect();
-        for entry in rss_entries {
-            feed = format!("{}{}", feed, entry)
+        kinds.sort_by_key(|k| k.to_string());
+        assert_eq!(kinds, ["not_a_file", "not_utf8"]);
+    }
+
+    #[test]
+    fn duplicate_slugs_are_not_published() {
+        let same = post("same", "2023-04-01", "public");
+        let dir = posts_dir(&[
+            ("a.md", same.clone()),
+            ("b.md", same),
+            ("c.md", post("unique", "2023-04-01", "public")),
+        ]);
+        let dir = dir.path();
+
+        let (posts, diagnostics) = load(dir, false, Utc::now());
+        assert_eq!(posts.len(), 1);
+        assert_eq!(posts[0].frontmatter.slug, "unique");
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].path, dir.join("a.md"));
+        assert!(matches!(
+            &diagnostics[0].error,
+            PostParseError::DuplicateSlug { slug, others } if slug == "same" && others == &vec![dir.join("b.md")]
+        ));
+
+        let (posts, diagnostics) = load(dir, true, Utc::now());
+        assert_eq!(posts.len(), 3);
+        assert_eq!(diagnostics.len(), 2);
+    }
+
+    #[test]
+    fn drafts_do_not_take_down_live_posts() {
+        let dir = posts_dir(&[
+            ("live.md", post("same", "2023-04-01", "public")),
+            ("private.md", post("same", "2023-04-01", "private")),
+            ("future.md", post("same", "2999-01-01", "public")),
+        ]);
+        let dir = dir.path();
+
+        let (posts, diagnostics) = load(dir, false, Utc::now());
+        assert_eq!(posts.len(), 1);
+        assert_eq!(posts[0].frontmatter.visibility, Visibility::Public);
+        assert_eq!(posts[0].frontmatter.published.to_string(), "2023-04-01");
+        let reported: Vec<_> = diagnostics.iter().map(|d| d.path.clone()).collect();
+        assert_eq!(reported, [dir.join("future.md"), dir.join("private.md")]);
+    }
+
+    #[test]
+    fn unlisted_posts_are_served_but_not_listed() {
+        let dir = posts_dir(&[
+            ("public.md", post("public", "2023-04-01", "public")),
+            ("unlisted.md", post("unlisted", "2023-04-01", "unlisted")),
+            ("private.md", post("private", "2023-04-01", "private")),
+        ]);
+        let (posts, diagnostics) = load(dir.path(), false, Utc::now());
+        let content = Content::new(posts, diagnostics, false, 20, Utc::now());
+
+        assert!(content.post_page("public").is_some());
+        assert!(content.post_page("unlisted").is_some());
+        assert!(content.post_page("private").is_none());
+        assert!(content.preview("private").is_some());
+        let text = |page: &Page| String::from_utf8(page.body(Encoding::Identity).to_vec()).unwrap();
+        for page in [
+            &content.post_list,
+            &content.sitemap,
+            &content.feeds.atom,
+            &content.feeds.rss,
+            &content.feeds.json,
+            content.tag_page("test").unwrap(),
+            &content.tag_feeds("test").unwrap().atom,
+        ] {
+            assert!(text(page).contains("/post/public"));
+            assert!(!text(page).contains("/post/unlisted"));
         }
-        format!("{}\n</feed>", feed)
-    }
-
-    /// This creates the initial atom feed.
-    /// Given a vec of posts it creates the atom feed based on what files
-    /// were on the disk at startup.
-    pub fn initialize_atom_feed(posts: Vec<Post>) -> String {
-        //let rss_entries: Vec<String> = Vec::new();
-        let mut feed = format!("{}", ATOM_HEADER);
-        let rss_entries: Vec<String> = posts.iter().map(|x| x.as_atom()).collect();
-        for entry in rss_entries {
-            feed = format!("{}{}", feed, entry)
+        assert!(text(content.post_page("unlisted").unwrap()).contains("noindex"));
+        assert!(!text(content.post_page("public").unwrap()).contains("noindex"));
+    }
+
+    #[test]
+    fn future_posts_wait_for_their_date() {
+        let dir = posts_dir(&[
+            ("out.md", post("out", "2023-06-01", "public")),
+            ("tomorrow.md", post("tomorrow", "2023-06-02", "unlisted")),
+            ("later.md", post("later", "2023-06-09", "public")),
+            ("never.md", post("never", "2023-06-02", "private")),
+        ]);
+        let now = "2023-06-01T12:00:00Z".parse().unwrap();
+        let (posts, diagnostics) = load(dir.path(), false, now);
+        let content = Content::new(posts, diagnostics, false, 20, now);
+
+        assert!(content.post("out").is_some());
+        assert!(content.post("tomorrow").is_none());
+        assert!(content.preview("tomorrow").is_some());
+        assert_eq!(
+            content.next_publication(),
+            Some("2023-06-02T00:00:00Z".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn posts_are_ordered_by_timestamp() {
+        let dir = posts_dir(&[
+            (
+                "morning.md",
+                post("morning", "2023-06-01T09:00:00+01:00", "public"),
+            ),
+            (
+                "evening.md",
+                post("evening", "2023-06-01T18:00:00+01:00", "public"),
+            ),
+            ("midnight.md", post("midnight", "2023-06-01", "public")),
+        ]);
+        let settings = SiteSettings {
+            posts_path: dir.path().to_path_buf(),
+            timezone: "Asia/Tokyo".parse().unwrap(),
+            ..Default::default()
+        };
+        let state = State::new(settings, "test".to_string(), false);
+        let content = state.content();
+        let slugs: Vec<_> = content
+            .posts
+            .iter()
+            .map(|p| p.frontmatter.slug.as_str())
+            .collect();
+        assert_eq!(slugs, ["evening", "morning", "midnight"]);
+        // Midnight in Tokyo is the afternoon before in UTC.
+        assert_eq!(
+            content.posts[2].published_at().to_rfc3339(),
+            "2023-05-31T15:00:00+00:00"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn scheduled_posts_go_out_on_time() {
+        let dir = posts_dir(&[("soon.md", post("soon", "2023-06-01T13:00:00Z", "public"))]);
+        let settings = SiteSettings {
+            posts_path: dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        // Time only moves when the paused runtime skips ahead to the next
+        // timer, so the post goes out the moment the scheduler wakes up.
+        let start: DateTime<Utc> = "2023-06-01T12:00:00Z".parse().unwrap();
+        let started = tokio::time::Instant::now();
+        let clock: Clock =
+            Arc::new(move || start + chrono::Duration::from_std(started.elapsed()).unwrap());
+        let state = Arc::new(State::with_clock(
+            settings,
+            "test".to_string(),
+            false,
+            clock,
+        ));
+        let soon = "2023-06-01T13:00:00Z".parse().unwrap();
+        assert!(state.content().post("soon").is_none());
+        assert_eq!(state.content().next_publication(), Some(soon));
+
+        let mut next = state.next_publication.subscribe();
+        state.publish_on_schedule();
+        next.changed().await.unwrap();
+        assert_eq!(started.elapsed(), std::time::Duration::from_secs(60 * 60));
+        let content = state.content();
+        assert!(content.post("soon").is_some());
+        for page in [&content.post_list, &content.sitemap, &content.feeds.json] {
+            let body = page.body(Encoding::Identity);
+            assert!(std::str::from_utf8(&body).unwrap().contains("/post/soon"));
+        }
+        assert_eq!(content.next_publication(), None);
+    }
+
+    #[tokio::test]
+    async fn extensions_survive_a_reload() {
+        let state = fixture_state();
+        state.render_cache.lock().unwrap().clear();
+        state.reload().await;
+
+        let footnotes = rendered_slug(&state, "footnotes").await;
+        assert!(footnotes.contains("class=\"footnotes\""));
+        assert!(footnotes.contains("<del>wrong</del>"));
+        assert!(footnotes.contains("<a href=\"https://whydoesntmycode.work\">"));
+
+        let tables = rendered_slug(&state, "tables").await;
+        assert!(tables.contains("<table>"));
+
+        let highlighting = rendered_slug(&state, "highlighting").await;
+        assert!(highlighting.contains("<pre style="));
+
+        let org = rendered_slug(&state, "org").await;
+        assert!(org.contains("<strong>bold</strong>"));
+        assert!(org.contains("<a href=\"https://orgmode.org\">link</a>"));
+        assert!(org.contains("class=\"footnotes\""));
+        assert!(org.contains("<table>"));
+        assert!(org.contains("<pre style="));
+
+        let bundle = rendered_slug(&state, "bundle").await;
+        assert!(bundle.contains("src=\"/post/bundle/pixel.png\""));
+        assert!(bundle.contains("href=\"/post/bundle/notes/pixel.txt\""));
+        assert!(bundle.contains("href=\"/blog\""));
+        assert!(bundle.contains("href=\"#a-bundle\""));
+        assert!(bundle.contains("href=\"https://nixos.org\""));
+
+        let notebook = rendered_slug(&state, "notebook").await;
+        assert!(notebook.contains("<pre style="));
+        assert!(notebook.contains("class=\"notebook-output\""));
+    }
+
+    #[tokio::test]
+    async fn listings_are_as_new_as_the_snapshot() {
+        // Taking a post down changes every listing it was on, so none of
+        // them can go by the dates of the posts that are left.
+        let state = fixture_state();
+        let built = Utc::now().timestamp();
+        state.reload().await;
+        let content = state.content();
+        let tag = content.tag_page("fixture").unwrap();
+        for page in [
+            &content.post_list,
+            &content.sitemap,
+            &content.feeds.rss,
+            tag,
+        ] {
+            let response = page.respond(StatusCode::OK, &HeaderMap::new());
+            let modified = response.headers()[LAST_MODIFIED].to_str().unwrap();
+            let modified = DateTime::parse_from_rfc2822(modified).unwrap();
+            assert!(modified.timestamp() >= built);
         }
-        format!("{}\n</feed>", feed)
+    }
+
+    #[test]
+    fn only_changed_posts_are_rendered_again() {
+        let dir = posts_dir(&[
+            ("same.md", post("same", "2023-04-01", "public")),
+            ("edited.md", post("edited", "2023-04-02", "public")),
+            ("deleted.md", post("deleted", "2023-04-03", "public")),
+        ]);
+        let renderer = Renderer::new(&SiteSettings::default());
+        let mut cache = RenderCache::new();
+        let load = |cache: &mut RenderCache| {
+            let posts_path = Some(dir.path().to_path_buf());
+            State::get_posts(posts_path, &renderer, Tz::UTC, false, Utc::now(), cache)
+        };
+        load(&mut cache);
+        assert_eq!(cache.len(), 3);
+
+        // Mark the renders we have, so we can tell which ones get reused.
+        for cached in cache.values_mut() {
+            cached.post.rendered = "cached".to_string();
+        }
+        let edited = post("edited", "2023-04-02", "public") + "Now with words.\n";
+        std::fs::write(dir.path().join("edited.md"), edited).unwrap();
+        std::fs::remove_file(dir.path().join("deleted.md")).unwrap();
+
+        let (posts, diagnostics) = load(&mut cache);
+        assert!(diagnostics.is_empty());
+        let rendered = |slug: &str| {
+            let post = posts.iter().find(|p| p.frontmatter.slug == slug).unwrap();
+            post.rendered.clone()
+        };
+        assert_eq!(rendered("same"), "cached");
+        assert!(rendered("edited").contains("Now with words."));
+        assert_eq!(posts.len(), 2);
+        assert!(!cache.contains_key(&dir.path().join("deleted.md")));
+        assert_eq!(cache.len(), 2);
     }
 }
@@ -0,0 +1,95 @@
+use crate::types::State;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::Path;
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+use tokio::time::Instant;
+use tracing::*;
+
+/// How long the posts directory has to be quiet before we rebuild.
+/// Editors and `git pull` touch files several times in quick succession, so we
+/// wait for the burst to settle instead of reloading on every event.
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// The longest we hold off after the first change, however busy the directory
+/// stays, so a long stream of writes can't keep the site stale.
+const MAX_DELAY: Duration = Duration::from_secs(2);
+
+/// Watch `path` and reload `state` whenever something under it changes.
+/// The returned watcher has to be kept alive, dropping it stops the watch and
+/// the reload task along with it.
+pub fn watch_posts(state: &Arc<State>, path: &Path) -> notify::Result<RecommendedWatcher> {
+    let (tx, rx) = unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |res: notify::Result<Event>| match res {
+        // Reads don't change anything, don't bother waking up for them.
+        Ok(event) if matches!(event.kind, EventKind::Access(_)) => {}
+        Ok(event) => {
+            let _ = tx.send(event);
+        }
+        Err(e) => error!("Error while watching posts: {:?}", e),
+    })?;
+    watcher.watch(path, RecursiveMode::Recursive)?;
+    info!("Watching {:?} for changes", path);
+    tokio::spawn(reload_on_change(Arc::downgrade(state), rx));
+    Ok(watcher)
+}
+
+async fn reload_on_change(state: Weak<State>, mut rx: UnboundedReceiver<Event>) {
+    while let Some(event) = rx.recv().await {
+        trace!("Change in posts dir: {:?}", event.paths);
+        // Soak up the rest of the burst before doing any work.
+        let deadline = Instant::now() + MAX_DELAY;
+        loop {
+            let quiet = Instant::now() + DEBOUNCE;
+            match tokio::time::timeout_at(quiet.min(deadline), rx.recv()).await {
+                Ok(Some(event)) => trace!("Change in posts dir: {:?}", event.paths),
+                _ => break,
+            }
+        }
+        match state.upgrade() {
+            Some(state) => {
+                info!("Posts changed on disk, reloading");
+                state.reload().await;
+            }
+            None => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, fixture_state, post, posts_dir};
+
+    #[tokio::test]
+    async fn changes_show_up_on_their_own() {
+        let dir = posts_dir(&[("old.md", post("old", "2023-04-01", "public"))]);
+        let state = testing::state(dir.path(), false);
+        let _watcher = watch_posts(&state, dir.path()).unwrap();
+
+        let new = post("new", "2023-04-02", "public");
+        std::fs::write(dir.path().join("new.md"), new).unwrap();
+        let deadline = Instant::now() + MAX_DELAY + Duration::from_secs(3);
+        while state.content().post("new").is_none() {
+            assert!(Instant::now() < deadline, "the new post never showed up");
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_steady_stream_of_changes_still_reloads() {
+        let state = fixture_state();
+        let before = state.content();
+        let (tx, rx) = unbounded_channel();
+        tokio::spawn(reload_on_change(Arc::downgrade(&state), rx));
+
+        let started = Instant::now();
+        while Arc::ptr_eq(&before, &state.content()) {
+            assert!(started.elapsed() <= MAX_DELAY + DEBOUNCE);
+            let _ = tx.send(Event::new(EventKind::Any));
+            tokio::time::sleep(DEBOUNCE / 5).await;
+        }
+        assert!(started.elapsed() >= MAX_DELAY);
+    }
+}
@@ -0,0 +1,91 @@
+<?xml version="1.0" encoding="UTF-8"?>
+<!--
+  Turns our Atom and RSS feeds into a page people can read when they open one
+  in a browser. Feed readers never look at this, they get the feed as is.
+  Browsers only do XSLT 1.0, so nothing fancier than that in here.
+-->
+<xsl:stylesheet version="1.0"
+                xmlns:xsl="http://www.w3.org/1999/XSL/Transform"
+                xmlns:atom="http://www.w3.org/2005/Atom"
+                xmlns:fh="http://purl.org/syndication/history/1.0"
+                exclude-result-prefixes="atom fh">
+  <xsl:output method="html" encoding="UTF-8" indent="yes" doctype-system="about:legacy-compat"/>
+
+  <xsl:template match="/">
+    <xsl:variable name="title" select="atom:feed/atom:title | rss/channel/title"/>
+    <xsl:variable name="subscribe">
+      <xsl:choose>
+        <xsl:when test="atom:feed/atom:link[@rel='current']">
+          <xsl:value-of select="atom:feed/atom:link[@rel='current']/@href"/>
+        </xsl:when>
+        <xsl:when test="atom:feed">
+          <xsl:value-of select="atom:feed/atom:link[@rel='self']/@href"/>
+        </xsl:when>
+        <xsl:otherwise>
+          <xsl:value-of select="rss/channel/atom:link[@rel='self']/@href"/>
+        </xsl:otherwise>
+      </xsl:choose>
+    </xsl:variable>
+    <html lang="en">
+      <head>
+        <meta name="robots" content="noindex"/>
+        <link rel="stylesheet" href="/static/css/debugdance.css"/>
+        <link rel="stylesheet" href="https://fonts.googleapis.com/css2?family=Fira+Code&amp;family=Fira+Sans&amp;display=swap"/>
+        <title><xsl:value-of select="$title"/> (Feed)</title>
+      </head>
+      <body>
+        <nav>
+          <a href="/">Home</a> - <a href="/blog">Blog</a> - <a href="/about">About Me</a> - <a href="/feeds">Feeds</a>
+        </nav>
+        <div class="feed-banner">
+          <h1>This is a feed</h1>
+          <p>
+            Feeds let you follow the blog without having to check back here.
+            Copy the address below into a feed reader and new posts will show up
+            there as they're published.
+          </p>
+          <pre><code><xsl:value-of select="$subscribe"/></code></pre>
+          <p>
+            New to feeds? <a href="https://aboutfeeds.com/">About Feeds</a>
+            explains what they are and has some readers worth trying.
+          </p>
+          <xsl:if test="atom:feed/fh:archive">
+            <p>
+              This page is an archive of older posts. The feed to subscribe to
+              is the one above.
+            </p>
+          </xsl:if>
+        </div>
+        <h2><xsl:value-of select="$title"/></h2>
+        <ul class="post-list">
+          <xsl:apply-templates select="atom:feed/atom:entry | rss/channel/item"/>
+        </ul>
+        <xsl:apply-templates select="atom:feed/atom:link[@rel='prev-archive' or @rel='next-archive']"/>
+      </body>
+    </html>
+  </xsl:template>
+
+  <xsl:template match="atom:entry">
+    <li class="post-link">
+      <span class="date"><xsl:value-of select="substring(atom:published, 1, 10)"/> -- </span>
+      <a href="{atom:link[@rel='alternate']/@href}"><xsl:value-of select="atom:title"/></a>
+      <p><xsl:value-of select="atom:summary"/></p>
+    </li>
+  </xsl:template>
+
+  <xsl:template match="item">
+    <li class="post-link">
+      <span class="date"><xsl:value-of select="substring(pubDate, 6, 11)"/> -- </span>
+      <a href="{link}"><xsl:value-of select="title"/></a>
+      <p><xsl:value-of select="description"/></p>
+    </li>
+  </xsl:template>
+
+  <xsl:template match="atom:link[@rel='prev-archive']">
+    <p><a href="{@href}">Older posts</a></p>
+  </xsl:template>
+
+  <xsl:template match="atom:link[@rel='next-archive']">
+    <p><a href="{@href}">Newer posts</a></p>
+  </xsl:template>
+</xsl:stylesheet>